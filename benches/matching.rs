@@ -0,0 +1,112 @@
+//! How long matching a listing against a query takes, for listings of a
+//! thousand, fifty thousand and half a million made up paths. Run with
+//! `cargo bench`, adding `--features nucleo` to compare nucleo to skim
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fdi::{update_fuzz, Matcher, OutputLine, Scorer, SortMode, Substring, Transform};
+use fuzzy_matcher::skim::SkimMatcherV2;
+
+const SIZES: [usize; 3] = [1_000, 50_000, 500_000];
+
+/// A short query, a directory name, a file name and one nothing matches
+const QUERIES: [&str; 4] = ["a", "src", "mod.rs", "xqzj"];
+
+const DIRS: [&str; 12] = [
+    "src",
+    "tests",
+    "docs",
+    "lib",
+    "assets",
+    "build",
+    "config",
+    "scripts",
+    "vendor",
+    "utils",
+    "node_modules",
+    "target",
+];
+const NAMES: [&str; 10] = [
+    "mod", "main", "index", "README", "config", "util", "parser", "render", "handler", "types",
+];
+const EXTENSIONS: [&str; 6] = ["rs", "md", "toml", "js", "json", "txt"];
+
+/// `count` paths from one to six directories deep, the same on every run
+fn paths(count: usize) -> Vec<String> {
+    // a fixed LCG rather than a random crate, so runs compare
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move |below: usize| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) as usize % below
+    };
+
+    (0..count)
+        .map(|nth| {
+            let mut path = String::new();
+            for _ in 0..1 + next(6) {
+                path.push_str(DIRS[next(DIRS.len())]);
+                path.push('/');
+            }
+            let name = NAMES[next(NAMES.len())];
+            let ext = EXTENSIONS[next(EXTENSIONS.len())];
+            path.push_str(&format!("{}{}.{}", name, nth % 100, ext));
+            path
+        })
+        .collect()
+}
+
+fn bench_scorer(c: &mut Criterion, name: &str, scorer: impl Fn() -> Box<dyn Scorer>) {
+    let matcher = Matcher {
+        scorer: scorer(),
+        min_score: 1,
+    };
+
+    for size in SIZES {
+        let mut group = c.benchmark_group(format!("{}/{}", name, size));
+        group.throughput(Throughput::Elements(size as u64));
+        if size >= 50_000 {
+            group.sample_size(10);
+        }
+
+        let mut unmatched: Vec<OutputLine> = paths(size)
+            .into_iter()
+            .map(|path| OutputLine::new(path, Transform::Full, false, &matcher, ""))
+            .collect();
+        let mut output = Vec::new();
+
+        for query in QUERIES {
+            group.bench_with_input(BenchmarkId::from_parameter(query), query, |b, query| {
+                // every run scores the whole listing again, whichever side
+                // of it each line ended up on last time
+                b.iter(|| {
+                    update_fuzz(
+                        &mut output,
+                        &mut unmatched,
+                        &matcher,
+                        query,
+                        SortMode::Auto,
+                        |_| false,
+                    )
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+fn matching(c: &mut Criterion) {
+    bench_scorer(c, "skim", || {
+        Box::new(SkimMatcherV2::default().smart_case())
+    });
+    bench_scorer(c, "exact", || Box::new(Substring { ignore_case: None }));
+    #[cfg(feature = "nucleo")]
+    bench_scorer(c, "nucleo", || {
+        Box::new(fdi::Nucleo {
+            case: nucleo_matcher::pattern::CaseMatching::Smart,
+        })
+    });
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
@@ -0,0 +1,2546 @@
+//! The matching, sorting and path handling behind fdi, kept apart from
+//! the terminal and the processes the binary drives
+use std::cmp::Ordering;
+use std::error::Error;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool, AtomicU64};
+use std::sync::Arc;
+
+use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use rayon::prelude::*;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// The part of a line the query is matched against, while the whole line
+/// is still what's displayed and navigated to
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Transform {
+    /// the whole line
+    #[default]
+    Full,
+    /// the last path component
+    Basename,
+    /// everything before the last path component
+    Dirname,
+    /// the line without the extension of its last component
+    StripExtension,
+}
+
+impl Transform {
+    pub fn parse(transform: &str) -> Result<Transform, Box<dyn Error>> {
+        match transform {
+            "full" => Ok(Transform::Full),
+            "basename" => Ok(Transform::Basename),
+            "dirname" => Ok(Transform::Dirname),
+            "strip-extension" => Ok(Transform::StripExtension),
+            _ => Err(format!("unknown transform: {}", transform).into()),
+        }
+    }
+
+    /// The byte range of `data` to match against
+    pub fn range(self, data: &str) -> Range<usize> {
+        let sep = std::path::MAIN_SEPARATOR;
+        // fd marks directories with a trailing separator in some modes
+        let trimmed = data.trim_end_matches(sep);
+
+        match self {
+            Transform::Full => 0..data.len(),
+            Transform::Basename => trimmed.rfind(sep).map_or(0, |i| i + 1)..trimmed.len(),
+            Transform::Dirname => 0..trimmed.rfind(sep).unwrap_or(0),
+            Transform::StripExtension => {
+                let base = Transform::Basename.range(data);
+
+                match data[base.clone()].rfind('.') {
+                    // a leading dot is a hidden file, not an extension
+                    Some(dot) if dot > 0 => 0..base.start + dot,
+                    _ => 0..data.len(),
+                }
+            }
+        }
+    }
+}
+
+/// A one-off ordering of the results by name, lasting until the query changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameSort {
+    Ascending,
+    Descending,
+}
+
+/// What the results are ordered by when no one-off name sort is active,
+/// switched between with Alt-S
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortMode {
+    /// by name for an empty query, where every score is the same, and by
+    /// score otherwise
+    #[default]
+    Auto,
+    Score,
+    /// the fewest path components first
+    Depth,
+    Name,
+}
+
+impl SortMode {
+    pub fn parse(mode: &str) -> Result<SortMode, Box<dyn Error>> {
+        match mode {
+            "auto" => Ok(SortMode::Auto),
+            "score" => Ok(SortMode::Score),
+            "depth" => Ok(SortMode::Depth),
+            "name" => Ok(SortMode::Name),
+            _ => Err(format!("unknown sort mode: {}", mode).into()),
+        }
+    }
+
+    /// The mode after this one, going round them all
+    pub fn next(self) -> SortMode {
+        match self {
+            SortMode::Auto => SortMode::Score,
+            SortMode::Score => SortMode::Depth,
+            SortMode::Depth => SortMode::Name,
+            SortMode::Name => SortMode::Auto,
+        }
+    }
+
+    /// How `a` compares to `b` in this order, as results for `pattern`
+    pub fn cmp(self, pattern: &str, a: &OutputLine, b: &OutputLine) -> Ordering {
+        match self {
+            SortMode::Auto if pattern.is_empty() => a.data.cmp(&b.data),
+            SortMode::Auto | SortMode::Score => a.cmp_rank(b),
+            SortMode::Depth => {
+                let depth = |line: &OutputLine| Path::new(&line.data).components().count();
+                depth(a).cmp(&depth(b)).then_with(|| a.data.cmp(&b.data))
+            }
+            SortMode::Name => a.data.cmp(&b.data),
+        }
+    }
+}
+
+/// A fuzzy matching algorithm, shared by the threads scoring a listing
+pub trait Scorer: Send + Sync {
+    /// The score of `haystack` for `needle` and the indices of the chars
+    /// that matched, if it matches at all
+    fn score_indices(&self, haystack: &str, needle: &str) -> Option<(i64, Vec<usize>)>;
+
+    /// Just the score, for ranking lines that may never be drawn, which
+    /// can skip tracking the matched chars
+    fn score(&self, haystack: &str, needle: &str) -> Option<i64> {
+        self.score_indices(haystack, needle).map(|(score, _)| score)
+    }
+}
+
+impl Scorer for SkimMatcherV2 {
+    fn score_indices(&self, haystack: &str, needle: &str) -> Option<(i64, Vec<usize>)> {
+        self.fuzzy_indices(haystack, needle)
+    }
+
+    fn score(&self, haystack: &str, needle: &str) -> Option<i64> {
+        self.fuzzy_match(haystack, needle)
+    }
+}
+
+/// Plain substring matching, for a query meant literally. The earlier in
+/// the line the query turns up, the higher the line scores
+pub struct Substring {
+    /// whether case is ignored, or with `None` ignored until the query
+    /// has an uppercase letter in it
+    pub ignore_case: Option<bool>,
+}
+
+impl Scorer for Substring {
+    fn score_indices(&self, haystack: &str, needle: &str) -> Option<(i64, Vec<usize>)> {
+        let needle: Vec<char> = needle.chars().collect();
+        if needle.is_empty() {
+            return Some((0, Vec::new()));
+        }
+
+        let ignore_case = self
+            .ignore_case
+            .unwrap_or_else(|| !needle.iter().any(|ch| ch.is_uppercase()));
+        let same = |a: char, b: char| {
+            if ignore_case {
+                a.to_lowercase().eq(b.to_lowercase())
+            } else {
+                a == b
+            }
+        };
+
+        let haystack: Vec<char> = haystack.chars().collect();
+        let start = haystack
+            .windows(needle.len())
+            .position(|window| window.iter().zip(&needle).all(|(&a, &b)| same(a, b)))?;
+
+        Some((
+            1000i64.saturating_sub(start as i64).max(1),
+            (start..start + needle.len()).collect(),
+        ))
+    }
+}
+
+/// nucleo's fuzzy matching, much quicker than skim's on big listings
+#[cfg(feature = "nucleo")]
+pub struct Nucleo {
+    pub case: nucleo_matcher::pattern::CaseMatching,
+}
+
+#[cfg(feature = "nucleo")]
+impl Nucleo {
+    /// Run `f` with this thread's matcher and `needle` parsed for it.
+    /// nucleo's matcher and the parsed needle are too costly to make for
+    /// every line, so each thread keeps its own for the last needle
+    fn with_atom<R>(
+        &self,
+        needle: &str,
+        f: impl FnOnce(&mut nucleo_matcher::Matcher, &nucleo_matcher::pattern::Atom) -> Option<R>,
+    ) -> Option<R> {
+        use nucleo_matcher::pattern::{Atom, AtomKind, Normalization};
+        use nucleo_matcher::Config;
+        use std::cell::RefCell;
+
+        thread_local! {
+            static STATE: RefCell<Option<(nucleo_matcher::Matcher, String, Atom)>> =
+                const { RefCell::new(None) };
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state.as_ref().is_none_or(|(_, last, _)| last != needle) {
+                let matcher = state
+                    .take()
+                    .map(|(matcher, _, _)| matcher)
+                    .unwrap_or_else(|| nucleo_matcher::Matcher::new(Config::DEFAULT.match_paths()));
+                let atom = Atom::new(
+                    needle,
+                    self.case,
+                    Normalization::Never,
+                    AtomKind::Fuzzy,
+                    false,
+                );
+                *state = Some((matcher, needle.to_string(), atom));
+            }
+            let (matcher, _, atom) = state.as_mut()?;
+
+            f(matcher, atom)
+        })
+    }
+}
+
+#[cfg(feature = "nucleo")]
+impl Scorer for Nucleo {
+    fn score_indices(&self, haystack: &str, needle: &str) -> Option<(i64, Vec<usize>)> {
+        use nucleo_matcher::Utf32Str;
+
+        if needle.is_empty() {
+            return Some((0, Vec::new()));
+        }
+
+        self.with_atom(needle, |matcher, atom| {
+            let mut chars = Vec::new();
+            let mut indices = Vec::new();
+            let score = atom.indices(Utf32Str::new(haystack, &mut chars), matcher, &mut indices)?;
+            indices.sort_unstable();
+            indices.dedup();
+
+            Some((
+                i64::from(score),
+                indices.into_iter().map(|i| i as usize).collect(),
+            ))
+        })
+    }
+
+    fn score(&self, haystack: &str, needle: &str) -> Option<i64> {
+        use nucleo_matcher::Utf32Str;
+
+        if needle.is_empty() {
+            return Some(0);
+        }
+
+        self.with_atom(needle, |matcher, atom| {
+            let mut chars = Vec::new();
+            atom.score(Utf32Str::new(haystack, &mut chars), matcher)
+                .map(i64::from)
+        })
+    }
+}
+
+/// The fuzzy matcher, along with how good a match has to be to count
+pub struct Matcher {
+    pub scorer: Box<dyn Scorer>,
+    /// the lowest score a line can have and still match a query, so that
+    /// a few chars strewn along a long path don't
+    pub min_score: i64,
+}
+
+/// How a line fared against the query, short of a score to rank it by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Miss {
+    /// the query isn't in it at all
+    NoMatch,
+    /// it's in there, but too spread out to score `min_score`
+    BelowMinScore,
+}
+
+impl Matcher {
+    /// The score of `line` for `pattern` and the indices of the chars
+    /// that matched, if it matches well enough
+    pub fn fuzzy_indices(&self, line: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+        self.scorer
+            .score_indices(line, pattern)
+            .filter(|(score, _)| pattern.is_empty() || *score >= self.min_score)
+    }
+
+    /// The score of `line` for `pattern`, if it matches well enough
+    pub fn fuzzy_score(&self, line: &str, pattern: &str) -> Option<i64> {
+        self.rate(line, pattern).ok()
+    }
+
+    /// The score of `line` for `pattern` if it matches well enough, or
+    /// else why it doesn't
+    pub fn rate(&self, line: &str, pattern: &str) -> Result<i64, Miss> {
+        match self.scorer.score(line, pattern) {
+            Some(score) if pattern.is_empty() || score >= self.min_score => Ok(score),
+            Some(_) => Err(Miss::BelowMinScore),
+            None => Err(Miss::NoMatch),
+        }
+    }
+}
+
+/// How a line fared against a query, kept apart from the line until
+/// every line has been rated, so rating can stop partway without leaving
+/// some lines scored for one query and the rest for another
+#[derive(Debug, Clone, Copy)]
+struct Rating {
+    score: i64,
+    matched: bool,
+    below_min_score: bool,
+}
+
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct OutputLine {
+    pub data: String,
+    /// what the query is matched against when a transform picks out
+    /// less than the whole of `data`
+    pub match_str: Option<String>,
+    /// how many chars of `data` come before `match_str`
+    pub match_offset: usize,
+    pub score: i64,
+    /// the chars the query matched, worked out by `highlight` only once
+    /// the line is drawn
+    pub indices: Vec<usize>,
+    /// whether the query matched at all, which an empty one always does
+    pub matched: bool,
+    /// whether the query only failed to match by scoring too low
+    pub below_min_score: bool,
+    /// whether `indices` are there for the query the line was scored for
+    pub highlighted: bool,
+}
+
+impl OutputLine {
+    pub fn new(
+        data: String,
+        transform: Transform,
+        fold: bool,
+        matcher: &Matcher,
+        match_with: &str,
+    ) -> OutputLine {
+        let mut score: i64 = Default::default();
+        let mut matched = false;
+        let mut below_min_score = false;
+
+        let range = transform.range(&data);
+        let match_offset = data[..range.start].chars().count();
+        let match_str = Some(&data[range])
+            .map(|match_str| {
+                if fold {
+                    fold_accents(match_str)
+                } else {
+                    match_str.to_string()
+                }
+            })
+            .filter(|match_str| match_str != &data);
+
+        let target = match_str.as_deref().unwrap_or(&data);
+        match matcher.rate(target, match_with) {
+            Ok(fscore) => {
+                score = fscore.saturating_add(basename_bonus(&data, match_with));
+                matched = true;
+            }
+            Err(miss) => below_min_score = miss == Miss::BelowMinScore,
+        }
+
+        OutputLine {
+            data,
+            match_str,
+            match_offset,
+            score,
+            indices: Vec::new(),
+            matched,
+            below_min_score,
+            highlighted: false,
+        }
+    }
+
+    pub fn update(&mut self, matcher: &Matcher, match_with: &str) -> &mut OutputLine {
+        let rating = self.rate(matcher, match_with);
+        self.apply(rating);
+
+        self
+    }
+
+    /// How the line fares against `match_with`, without scoring it yet
+    fn rate(&self, matcher: &Matcher, match_with: &str) -> Rating {
+        let target = self.match_str.as_deref().unwrap_or(&self.data);
+
+        match matcher.rate(target, match_with) {
+            Ok(fscore) => Rating {
+                score: fscore.saturating_add(basename_bonus(&self.data, match_with)),
+                matched: true,
+                below_min_score: false,
+            },
+            Err(miss) => Rating {
+                score: Default::default(),
+                matched: false,
+                below_min_score: miss == Miss::BelowMinScore,
+            },
+        }
+    }
+
+    /// Score the line as `rating` has it
+    fn apply(&mut self, rating: Rating) {
+        self.score = rating.score;
+        self.matched = rating.matched;
+        self.below_min_score = rating.below_min_score;
+        self.indices.clear();
+        self.highlighted = false;
+    }
+
+    /// Work out which chars `match_with` matched, for drawing the line,
+    /// unless that's been done since it was last scored
+    pub fn highlight(&mut self, matcher: &Matcher, match_with: &str) {
+        if self.highlighted || !self.matched {
+            return;
+        }
+
+        let target = self.match_str.as_deref().unwrap_or(&self.data);
+        if let Some((_, indices)) = matcher.fuzzy_indices(target, match_with) {
+            self.indices = indices.into_iter().map(|i| i + self.match_offset).collect();
+        }
+        self.highlighted = true;
+    }
+
+    /// How this line ranks against `other`, best first. Equal scores go to
+    /// the shorter path and then the one first by name, so results that tie
+    /// keep their places however the listing came in. The derived `Ord`
+    /// goes by `data` first and isn't a ranking
+    pub fn cmp_rank(&self, other: &OutputLine) -> Ordering {
+        other
+            .score
+            .cmp(&self.score)
+            .then_with(|| self.data.chars().count().cmp(&other.data.chars().count()))
+            .then_with(|| self.data.cmp(&other.data))
+    }
+
+    /// The line as the full path under `dir`, its matches moved along
+    /// past the directory put in front
+    pub fn under(&self, dir: &Path) -> OutputLine {
+        let data = dir.join(&self.data).to_string_lossy().into_owned();
+        let shift = data
+            .chars()
+            .count()
+            .saturating_sub(self.data.chars().count());
+
+        OutputLine {
+            data,
+            match_str: self.match_str.clone(),
+            match_offset: self.match_offset + shift,
+            score: self.score,
+            indices: self.indices.iter().map(|i| i + shift).collect(),
+            matched: self.matched,
+            below_min_score: self.below_min_score,
+            highlighted: self.highlighted,
+        }
+    }
+
+    pub fn display(&self, options: &DisplayOptions) -> String {
+        self.display_rows(options, 1).remove(0)
+    }
+
+    /// Draw the line wrapped onto rows of `options.width` chars, at
+    /// most `max_rows` of them and always at least one
+    pub fn display_rows(&self, options: &DisplayOptions, max_rows: usize) -> Vec<String> {
+        let skip = options
+            .home
+            .map_or(0, |home| home_prefix_len(&self.data, home));
+
+        let tilde = Some(if skip <= options.dim {
+            Shade::Dim
+        } else {
+            Shade::Plain
+        })
+        .filter(|_| skip > 0)
+        .map(|shade| (shade, '~'));
+
+        let chars = self
+            .data
+            .char_indices()
+            .enumerate()
+            .skip_while(|(_, (i, _))| *i < skip)
+            .map(|(pos, (i, ch))| {
+                // the match indices count chars, while `skip` and `dim`
+                // count bytes
+                let found = self.indices.iter().position(|&idx| idx == pos);
+                // a control char in a name, like a `\r` or a tab, would
+                // move the cursor, so it takes a column as a placeholder
+                let drawn = if ch.is_control() { '\u{fffd}' } else { ch };
+
+                if let Some(nth) = found {
+                    // color the character, telling apart the chars that only
+                    // matched their query char when ignoring case
+                    let exact = options.pattern.chars().nth(nth) == Some(ch);
+
+                    match options.case_shade {
+                        Some(value) if !exact => (Shade::LooseMatch(value), drawn),
+                        _ => (Shade::Match, drawn),
+                    }
+                } else if i < options.dim {
+                    (Shade::Dim, drawn)
+                } else {
+                    (Shade::Plain, drawn)
+                }
+            });
+
+        // a line that doesn't fit is cut to leave room for a `…` where the
+        // cut is, counting chars since only they take up columns
+        let room = options.width * max_rows.max(1);
+        let mut chars: Vec<(Shade, char)> = tilde.into_iter().chain(chars).collect();
+        if !options.color {
+            chars = bracket_matches(chars);
+        }
+        if chars.len() > room && room > 0 {
+            let cut = (Shade::Dim, '…');
+            let whole = chars.clone();
+
+            match options.ellipsis {
+                Ellipsis::End => {
+                    chars.truncate(room - 1);
+                    chars.push(cut);
+                }
+                Ellipsis::Middle => {
+                    let head = (room - 1) / 2;
+                    let tail = room - 1 - head;
+                    chars.splice(head..chars.len() - tail, Some(cut));
+                }
+            }
+
+            // a cut that leaves none of the matches showing gives way to
+            // the stretch of the line they're in, so there's always a
+            // highlight to see
+            let first = whole.iter().position(|(shade, _)| shade.is_match());
+            let last = whole.iter().rposition(|(shade, _)| shade.is_match());
+            let shown = chars.iter().any(|(shade, _)| shade.is_match());
+            if let (Some(first), Some(last), false) = (first, last, shown) {
+                if room >= 3 {
+                    // the brackets drawn without color go with their match
+                    let (first, last) = if options.color {
+                        (first, last)
+                    } else {
+                        (first.saturating_sub(1), last + 1)
+                    };
+                    chars = match_window(&whole, first, last, room, cut);
+                }
+            }
+        }
+
+        // only switch colors between runs of differently shaded chars, and
+        // past `max_runs` switches draw the rest plain so a line matching
+        // on every char can't blow up into an escape per char. Each row
+        // of a wrapped line opens with its color again, being drawn apart
+        let reset = match options.base {
+            _ if !options.color => String::new(),
+            Some(_) => ATTRS_OFF.to_string(),
+            None => Shade::Plain.escape(&options.theme, None),
+        };
+        let mut rows = Vec::new();
+        let mut line = String::new();
+        let mut current = None;
+        let mut runs = 0;
+        let mut col = 0;
+        for (mut shade, ch) in chars.into_iter().take(room) {
+            if col == options.width {
+                line.push_str(&reset);
+                rows.push(std::mem::take(&mut line));
+                current = None;
+                col = 0;
+            }
+
+            if runs >= options.max_runs {
+                shade = Shade::Plain;
+            }
+
+            if current != Some(shade) && options.color {
+                line.push_str(&shade.escape(&options.theme, options.base));
+                current = Some(shade);
+                runs += 1;
+            }
+
+            line.push(ch);
+            col += 1;
+        }
+
+        // always end on a reset, even when the last visible char was a
+        // match or the highlighted char got cut off by the truncation
+        line.push_str(&reset);
+        rows.push(line);
+
+        rows
+    }
+}
+
+/// The `room` chars of `chars` to draw so that the one at `first` shows,
+/// and as many as fit up to `last`, with `cut` standing in for what's
+/// left out before them and after them
+fn match_window(
+    chars: &[(Shade, char)],
+    first: usize,
+    last: usize,
+    room: usize,
+    cut: (Shade, char),
+) -> Vec<(Shade, char)> {
+    let mut window = vec![cut];
+
+    // the end of the line fits after the leading cut
+    if chars.len() - first < room {
+        window.extend_from_slice(&chars[chars.len() - (room - 1)..]);
+        return window;
+    }
+
+    let start = (last + 1).saturating_sub(room - 2).min(first);
+    window.extend_from_slice(&chars[start..start + room - 2]);
+    window.push(cut);
+
+    window
+}
+
+/// Put each run of matched chars between `[` and `]`, for drawing the
+/// matches without color
+fn bracket_matches(chars: Vec<(Shade, char)>) -> Vec<(Shade, char)> {
+    let mut bracketed = Vec::with_capacity(chars.len());
+    let mut open = false;
+    for (shade, ch) in chars {
+        if shade.is_match() != open {
+            open = !open;
+            bracketed.push((Shade::Plain, if open { '[' } else { ']' }));
+        }
+        bracketed.push((shade, ch));
+    }
+    if open {
+        bracketed.push((Shade::Plain, ']'));
+    }
+
+    bracketed
+}
+
+/// Added to the score of a line whose file name starts with the query
+pub const BASENAME_PREFIX_BONUS: i64 = 20;
+/// Added instead when the file name is the query, with or without its
+/// extension, trailing `/` aside
+pub const BASENAME_EXACT_BONUS: i64 = 40;
+
+/// The bonus for how well the file name of `line` alone agrees with
+/// `pattern`, ignoring case, since the skim score barely tells
+/// `main.rs` and `src/main.rs` apart for `main`
+pub fn basename_bonus(line: &str, pattern: &str) -> i64 {
+    if pattern.is_empty() {
+        return 0;
+    }
+
+    let trimmed = line.trim_end_matches(std::path::MAIN_SEPARATOR);
+    let name = trimmed
+        .rsplit(std::path::MAIN_SEPARATOR)
+        .next()
+        .unwrap_or(trimmed)
+        .to_lowercase();
+    let pattern = pattern.to_lowercase();
+
+    let stem = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[..dot],
+        _ => &name[..],
+    };
+
+    if name == pattern || stem == pattern {
+        BASENAME_EXACT_BONUS
+    } else if name.starts_with(&pattern) {
+        BASENAME_PREFIX_BONUS
+    } else {
+        0
+    }
+}
+
+/// Swap each accented char in `s` for its base letter, leaving the chars
+/// that don't come apart into a single letter and marks as they are so
+/// match indices still line up with the original
+pub fn fold_accents(s: &str) -> String {
+    s.chars()
+        .map(|ch| {
+            let mut base = std::iter::once(ch).nfd().filter(|c| !is_combining_mark(*c));
+
+            match (base.next(), base.next()) {
+                (Some(base), None) => base,
+                _ => ch,
+            }
+        })
+        .collect()
+}
+
+/// The colors a displayed char can be drawn in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shade {
+    Plain,
+    Match,
+    /// a match that only holds ignoring case, in this ANSI color
+    LooseMatch(u8),
+    Dim,
+}
+
+/// Turns off everything an LS_COLORS style can set but the background,
+/// which the selected result's row is drawn with
+const ATTRS_OFF: &str = "\x1b[22;23;24;25;27;39m";
+
+impl Shade {
+    pub fn is_match(self) -> bool {
+        matches!(self, Shade::Match | Shade::LooseMatch(_))
+    }
+
+    /// The escape to draw in this shade, plain chars in the `base` style
+    /// when they have one and everything else over it with it turned off.
+    /// Matches are bold as well as colored, to stand out on any background
+    pub fn escape(self, theme: &Theme, base: Option<&str>) -> String {
+        let bold = SetAttribute(Attribute::Bold);
+        let normal = SetAttribute(Attribute::NormalIntensity);
+
+        match self {
+            Shade::Plain => match base {
+                Some(base) => format!("{}\x1b[{}m", ATTRS_OFF, base),
+                None => format!("{}{}", normal, SetForegroundColor(Color::Reset)),
+            },
+            _ if base.is_some() => format!("{}{}", ATTRS_OFF, self.escape(theme, None)),
+            Shade::Match => format!(
+                "{}{}",
+                bold,
+                SetForegroundColor(Color::AnsiValue(theme.matched))
+            ),
+            Shade::LooseMatch(value) => {
+                format!("{}{}", bold, SetForegroundColor(Color::AnsiValue(value)))
+            }
+            Shade::Dim => format!("{}{}", normal, SetForegroundColor(Color::DarkGrey)),
+        }
+    }
+}
+
+/// The colors the UI is drawn in, as ANSI color indices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// the matched chars of a result
+    pub matched: u8,
+    /// the background of the selected result
+    pub selected: u8,
+    /// the prompt before the query, the terminal's own color if unset
+    pub prompt: Option<u8>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            matched: 1,
+            selected: 8,
+            prompt: None,
+        }
+    }
+}
+
+/// What `OutputLine::display` needs to know about the render it's part of
+pub struct DisplayOptions<'a> {
+    /// the most chars to draw
+    pub width: usize,
+    /// draw the first `dim` bytes dimmed, unless they're part of a match
+    pub dim: usize,
+    /// the home directory to abbreviate to `~`, if it should be
+    pub home: Option<&'a str>,
+    /// the most color switches to draw in one line
+    pub max_runs: usize,
+    /// the query the match indices are for
+    pub pattern: &'a str,
+    /// the ANSI color for chars that matched ignoring case, if they
+    /// should stand out from exact matches
+    pub case_shade: Option<u8>,
+    /// which part of a line too long to fit gives way to `…`
+    pub ellipsis: Ellipsis,
+    /// the colors to draw in
+    pub theme: Theme,
+    /// the SGR parameters from LS_COLORS for the line's plain chars
+    pub base: Option<&'a str>,
+    /// draw the shades with escapes, or else none at all and each run of
+    /// matched chars in brackets
+    pub color: bool,
+}
+
+/// The styles $LS_COLORS gives each kind of entry, as SGR parameters
+#[derive(Debug, Clone, Default)]
+pub struct LsColors {
+    /// by two-letter kind, like `di` for directories
+    kinds: Vec<(String, String)>,
+    /// by the end of the file name, like `.tar.gz` from `*.tar.gz`
+    suffixes: Vec<(String, String)>,
+}
+
+impl LsColors {
+    /// The styles in `$LS_COLORS`, `None` when it isn't set
+    pub fn from_env() -> Option<LsColors> {
+        std::env::var("LS_COLORS")
+            .ok()
+            .filter(|colors| !colors.is_empty())
+            .map(|colors| LsColors::parse(&colors))
+    }
+
+    /// Parse `key=style` entries separated by `:`, leaving out background
+    /// colors so the selected row's still shows through
+    pub fn parse(colors: &str) -> LsColors {
+        let mut parsed = LsColors::default();
+
+        for entry in colors.split(':') {
+            let (key, style) = match entry.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let style = foreground_only(style);
+            if style.is_empty() {
+                continue;
+            }
+
+            match key.strip_prefix('*') {
+                Some(suffix) => parsed.suffixes.push((suffix.to_string(), style)),
+                None => parsed.kinds.push((key.to_string(), style)),
+            }
+        }
+
+        // the longest suffix that fits wins, like `.tar.gz` over `.gz`
+        parsed
+            .suffixes
+            .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        parsed
+    }
+
+    fn kind(&self, kind: &str) -> Option<&str> {
+        self.kinds
+            .iter()
+            .find(|(key, _)| key == kind)
+            .map(|(_, style)| style.as_str())
+    }
+
+    /// The style for the entry at `path`, looked up by what it is on disk
+    /// and, for plain files, by its name
+    pub fn style(&self, path: &Path) -> Option<&str> {
+        let meta = path.symlink_metadata().ok()?;
+        let file_type = meta.file_type();
+
+        if file_type.is_symlink() {
+            return match path.metadata() {
+                Err(_) => self.kind("or").or_else(|| self.kind("ln")),
+                Ok(_) => self.kind("ln"),
+            };
+        }
+        if file_type.is_dir() {
+            return self.kind("di");
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+            let special = if file_type.is_fifo() {
+                Some("pi")
+            } else if file_type.is_socket() {
+                Some("so")
+            } else if file_type.is_block_device() {
+                Some("bd")
+            } else if file_type.is_char_device() {
+                Some("cd")
+            } else {
+                None
+            };
+            if let Some(kind) = special {
+                return self.kind(kind);
+            }
+            if meta.permissions().mode() & 0o111 != 0 {
+                if let Some(style) = self.kind("ex") {
+                    return Some(style);
+                }
+            }
+        }
+
+        let name = path.file_name()?.to_string_lossy();
+        self.suffixes
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(_, style)| style.as_str())
+            .or_else(|| self.kind("fi"))
+    }
+}
+
+/// The SGR parameters in `style` without the background colors
+fn foreground_only(style: &str) -> String {
+    let mut kept = Vec::new();
+    let mut params = style.split(';').filter(|param| !param.is_empty());
+
+    while let Some(param) = params.next() {
+        match param.parse::<u8>() {
+            // a 256 or true color background takes the values after it
+            Ok(48) => match params.next() {
+                Some("5") => {
+                    params.next();
+                }
+                Some("2") => {
+                    params.nth(2);
+                }
+                _ => {}
+            },
+            Ok(40..=47) | Ok(49) | Ok(100..=107) => {}
+            // a 256 or true color foreground keeps its values
+            Ok(38) => {
+                kept.push(param);
+                let values = match params.next() {
+                    Some("5") => {
+                        kept.push("5");
+                        1
+                    }
+                    Some("2") => {
+                        kept.push("2");
+                        3
+                    }
+                    _ => 0,
+                };
+                kept.extend(params.by_ref().take(values));
+            }
+            Ok(_) => kept.push(param),
+            Err(_) => {}
+        }
+    }
+
+    kept.join(";")
+}
+
+/// Where a displayed line too long for its room is cut short
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Ellipsis {
+    /// keep the start of the line, losing the end
+    #[default]
+    End,
+    /// keep the start and the end, losing the middle, so the file name
+    /// stays in view at the end of a deep path
+    Middle,
+}
+
+impl Ellipsis {
+    pub fn parse(ellipsis: &str) -> Result<Ellipsis, Box<dyn Error>> {
+        match ellipsis {
+            "end" => Ok(Ellipsis::End),
+            "middle" => Ok(Ellipsis::Middle),
+            _ => Err(format!("unknown ellipsis: {}", ellipsis).into()),
+        }
+    }
+}
+
+/// The home directory, if there is one worth abbreviating
+pub fn home_dir() -> Option<String> {
+    let home = std::env::var("HOME");
+    #[cfg(windows)]
+    let home = home.or_else(|_| std::env::var("USERPROFILE"));
+
+    home.ok()
+        .map(|home| home.trim_end_matches(std::path::MAIN_SEPARATOR).to_string())
+        .filter(|home| !home.is_empty())
+}
+
+/// Expand a leading `~` to the home directory and `$VAR` or `${VAR}` to
+/// the variable's value in a typed path, leaving anything that can't be
+/// expanded as it was typed
+pub fn expand_path(input: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = input;
+
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(std::path::is_separator) {
+            if let Some(home) = home_dir() {
+                expanded.push_str(&home);
+                rest = after;
+            }
+        }
+    }
+
+    while let Some(at) = rest.find('$') {
+        expanded.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        match std::env::var(name).ok().filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[at..at + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/// Where the `chars`th char of `text` starts, or its end past the last one
+pub fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// `input` with its `at`th char taken out, however many bytes it takes,
+/// or as it is when there's no such char
+pub fn remove_char(input: &str, at: usize) -> String {
+    let mut edited = input.to_string();
+    if at < input.chars().count() {
+        edited.remove(byte_offset(input, at));
+    }
+    edited
+}
+
+/// The query with its last word deleted like readline's Ctrl-w, stopping
+/// at a path separator as well as whitespace so `src/main` goes back to
+/// `src/`, and `src/` (separators after the word and all) to nothing
+pub fn delete_word(input: &str) -> &str {
+    let separator = |c: char| c == '/' || std::path::is_separator(c);
+
+    let rest = input.trim_end_matches(char::is_whitespace);
+    let rest = rest.trim_end_matches(separator);
+    rest.trim_end_matches(|c: char| !c.is_whitespace() && !separator(c))
+}
+
+/// How many leading bytes of `path` are the home directory `home`,
+/// 0 when `path` isn't inside it
+pub fn home_prefix_len(path: &str, home: &str) -> usize {
+    match path.strip_prefix(home) {
+        Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => home.len(),
+        _ => 0,
+    }
+}
+
+/// `path` with a leading home directory abbreviated to `~` like shells do
+pub fn abbreviate_home(path: &str, home: &str) -> String {
+    match home_prefix_len(path, home) {
+        0 => path.to_string(),
+        len => format!("~{}", &path[len..]),
+    }
+}
+
+/// `dir` shortened to at most `width` chars by swapping directories from
+/// the middle for a `…`, though its start and last two directories are
+/// always kept, like `/home/…/project/src`
+pub fn breadcrumb(dir: &str, width: usize) -> String {
+    if dir.chars().count() <= width {
+        return dir.to_string();
+    }
+
+    let sep = std::path::MAIN_SEPARATOR.to_string();
+    let parts: Vec<&str> = dir.split(std::path::MAIN_SEPARATOR).collect();
+    if parts.len() <= 3 {
+        return dir.to_string();
+    }
+
+    let (head, rest) = parts.split_at(1);
+    let (middle, tail) = rest.split_at(rest.len() - 2);
+
+    // keep as many of the leading directories as still fit
+    let crumb = |kept: usize| {
+        let mut crumb = head.to_vec();
+        crumb.extend(&middle[..kept]);
+        crumb.push("…");
+        crumb.extend(tail);
+        crumb.join(&sep)
+    };
+    (0..middle.len())
+        .rev()
+        .map(crumb)
+        .find(|crumb| crumb.chars().count() <= width)
+        .unwrap_or_else(|| crumb(0))
+}
+
+/// The text to draw before and after the query for a prompt `template`,
+/// where `{input}` marks the query's place, the end when it's left out,
+/// and each `{dir}` is `dir` cut down so the line fits in `width`
+pub fn render_prompt(
+    template: &str,
+    dir: &str,
+    input_len: usize,
+    width: usize,
+) -> (String, String) {
+    let (before, after) = template.split_once("{input}").unwrap_or((template, ""));
+
+    // what the template takes up around the directory and the query,
+    // and a column for the terminal's cursor after the query
+    let dirs = template.matches("{dir}").count();
+    let fixed = before.chars().count() + after.chars().count() - dirs * "{dir}".len();
+    let room = width.saturating_sub(fixed + input_len + 1) / dirs.max(1);
+    let dir = breadcrumb(dir, room);
+
+    (before.replace("{dir}", &dir), after.replace("{dir}", &dir))
+}
+
+/// `bytes` in the largest unit that keeps it at least 1, like `4.2K`
+/// or `310M`, with a decimal only below 10
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, UNITS[unit])
+    }
+}
+
+/// How long ago something happened `age` back, in its largest whole
+/// unit, like `5m ago` or `3d ago`
+pub fn human_age(age: std::time::Duration) -> String {
+    const UNITS: [(u64, &str); 6] = [
+        (365 * 24 * 3600, "y"),
+        (30 * 24 * 3600, "mo"),
+        (7 * 24 * 3600, "w"),
+        (24 * 3600, "d"),
+        (3600, "h"),
+        (60, "m"),
+    ];
+
+    let secs = age.as_secs();
+    match UNITS.iter().find(|(unit, _)| secs >= *unit) {
+        Some((unit, name)) => format!("{}{} ago", secs / unit, name),
+        None => "just now".to_string(),
+    }
+}
+
+/// The length in bytes of the leading directories every one of `lines`
+/// shares, up to and including the last separator
+pub fn common_prefix_len<'a>(mut lines: impl Iterator<Item = &'a str>) -> usize {
+    let first = match lines.next() {
+        Some(first) => first,
+        None => return 0,
+    };
+
+    let mut len = first.len();
+    for line in lines {
+        len = first
+            .char_indices()
+            .zip(line.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+
+    first[..len]
+        .rfind(std::path::MAIN_SEPARATOR)
+        .map_or(0, |sep| sep + 1)
+}
+
+/// Map an entry of the --from-file list, written relative to the directory
+/// fdi started in, onto the current `dir`. At the start directory every
+/// entry is kept as is; elsewhere only the entries under `dir` are kept,
+/// relative to it, so descending and ascending still behave like paths
+pub fn list_entry(base: &Path, dir: &Path, entry: String) -> Option<String> {
+    if dir == base {
+        return Some(entry);
+    }
+
+    base.join(&entry)
+        .strip_prefix(dir)
+        .ok()
+        .filter(|rel| !rel.as_os_str().is_empty())
+        .map(|rel| rel.to_string_lossy().into_owned())
+}
+
+/// The file and line of an rg hit, `path:line:text`, or `None` for a
+/// line that isn't one
+pub fn grep_hit(line: &str) -> Option<(&str, u64)> {
+    line.match_indices(':').find_map(|(at, _)| {
+        let (number, _) = line[at + 1..].split_once(':')?;
+        Some((&line[..at], number.parse().ok()?))
+    })
+}
+
+/// `path` made absolute with every symlink in it resolved, like std's
+/// canonicalize but without the `\\?\` prefix that Windows puts on it,
+/// which is only there for its file APIs and not something to show
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    path.canonicalize().map(strip_verbatim)
+}
+
+#[cfg(windows)]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    let text = match path.to_str() {
+        Some(text) => text,
+        None => return path,
+    };
+
+    if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        return PathBuf::from(format!(r"\\{}", share));
+    }
+    match text.strip_prefix(r"\\?\") {
+        // only drive paths, anything else needs the prefix to mean the same
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => PathBuf::from(rest),
+        _ => path,
+    }
+}
+
+#[cfg(not(windows))]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// Resolve `.` and `..` in `path` without touching the filesystem,
+/// so any symlinks in it are kept as they are
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+
+    normal
+}
+
+/// `path` as seen from `base`, stepping up with `..` for the parts of
+/// `base` it isn't under. Both are taken to be normalized already
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base.components().peekable();
+
+    while let (Some(a), Some(b)) = (path_parts.peek(), base_parts.peek()) {
+        if a != b {
+            break;
+        }
+        path_parts.next();
+        base_parts.next();
+    }
+
+    let relative: PathBuf = base_parts
+        .map(|_| Component::ParentDir)
+        .chain(path_parts)
+        .collect();
+
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+/// `path` single quoted for a POSIX shell, so nothing in it is expanded
+/// or split, with each `'` in it closing the quotes for an escaped `'` of
+/// its own
+pub fn shell_quote(path: &Path) -> Vec<u8> {
+    let mut quoted = b"'".to_vec();
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// A shell command changing to `dir`, quoted so its name comes through
+/// as it is
+pub fn cd_command(dir: &Path) -> Vec<u8> {
+    let mut command = b"cd -- ".to_vec();
+    command.extend_from_slice(&shell_quote(dir));
+    command.push(b'\n');
+    command
+}
+
+/// Why a directory couldn't be gone into, for the status line, from
+/// looking it up: whether what's there is a directory, or why nothing
+/// could be found, and whether there's a link by that name at least
+pub fn bad_dir_reason(lookup: std::io::Result<bool>, link: bool) -> &'static str {
+    match lookup {
+        Ok(false) => "not a directory",
+        Ok(true) => "can't open directory",
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => "permission denied",
+        Err(_) if link => "broken link",
+        Err(_) => "no such directory",
+    }
+}
+
+/// Whether reading a directory failed for not being allowed to, which
+/// leaves fd listing nothing, rather than it being gone
+pub fn is_permission_denied<T>(read: &std::io::Result<T>) -> bool {
+    read.as_ref()
+        .is_err_and(|err| err.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// Whether the listed `entry` is just `dir` again, as `.` or its own
+/// full path
+pub fn is_dir_itself(dir: &Path, entry: &str) -> bool {
+    normalize_lexically(&dir.join(entry)) == dir
+}
+
+/// How many lines there have to be before they are scored in parallel
+const PARALLEL_SCORING_MIN: usize = 4096;
+
+/// How many lines are scored between checks of whether the pattern is
+/// still wanted
+const SCORING_CHUNK: usize = 1024;
+
+/// Match every line of the listing against `pattern`, the ones it matches
+/// in `output` in `sort` order and the rest set aside in `unmatched`,
+/// other than those `keep` holds on to
+pub fn update_fuzz(
+    output: &mut Vec<OutputLine>,
+    unmatched: &mut Vec<OutputLine>,
+    matcher: &Matcher,
+    pattern: &str,
+    sort: SortMode,
+    keep: impl Fn(&OutputLine) -> bool,
+) {
+    update_fuzz_unless(output, unmatched, matcher, pattern, sort, keep, || false);
+}
+
+/// `update_fuzz`, giving up on a big listing once `stale` says a newer
+/// pattern is on its way, which is checked between chunks of lines.
+/// Whether it finished; if not, `output` and `unmatched` are left just
+/// as they were
+pub fn update_fuzz_unless(
+    output: &mut Vec<OutputLine>,
+    unmatched: &mut Vec<OutputLine>,
+    matcher: &Matcher,
+    pattern: &str,
+    sort: SortMode,
+    keep: impl Fn(&OutputLine) -> bool,
+    stale: impl Fn() -> bool + Sync,
+) -> bool {
+    // spreading the scoring over threads only pays off for big listings
+    if output.len() + unmatched.len() >= PARALLEL_SCORING_MIN {
+        let rate = |lines: &[OutputLine]| -> Option<Vec<Vec<Rating>>> {
+            lines
+                .par_chunks(SCORING_CHUNK)
+                .map(|chunk| {
+                    if stale() {
+                        return None;
+                    }
+                    Some(
+                        chunk
+                            .iter()
+                            .map(|line| line.rate(matcher, pattern))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+        let ratings = match (rate(output), rate(unmatched)) {
+            (Some(output), Some(unmatched)) => output.into_iter().chain(unmatched),
+            _ => return false,
+        };
+
+        let lines = output.iter_mut().chain(unmatched.iter_mut());
+        for (line, rating) in lines.zip(ratings.flatten()) {
+            line.apply(rating);
+        }
+    } else {
+        for line in output.iter_mut().chain(unmatched.iter_mut()) {
+            line.update(matcher, pattern);
+        }
+    }
+    output.append(unmatched);
+    set_aside_unmatched(output, unmatched, keep);
+
+    output.sort_by(|a, b| sort.cmp(pattern, a, b));
+    true
+}
+
+/// Move the lines the query doesn't match out of `output` into
+/// `unmatched`, other than those `keep` holds on to
+pub fn set_aside_unmatched(
+    output: &mut Vec<OutputLine>,
+    unmatched: &mut Vec<OutputLine>,
+    keep: impl Fn(&OutputLine) -> bool,
+) {
+    let (kept, rest): (Vec<_>, Vec<_>) = std::mem::take(output)
+        .into_iter()
+        .partition(|line| line.matched || keep(line));
+
+    *output = kept;
+    unmatched.extend(rest);
+}
+
+/// Handed out with each listing and set once it's given up on, like when
+/// another directory is gone into, so that lines it still had on their
+/// way are dropped rather than taken for those of the listing after it.
+/// Its clones are all set together
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Give up on the listing
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::SeqCst)
+    }
+
+    /// `line` as long as the listing it came from is still wanted
+    pub fn admit<T>(&self, line: Option<T>) -> Option<T> {
+        line.filter(|_| !self.is_cancelled())
+    }
+}
+
+/// Counts up with each piece of work handed off to be done elsewhere,
+/// like matching the results, and with each change that leaves the one
+/// underway out of date, so it can stop partway and what it comes to can
+/// be told apart from newer work. Its clones all count together
+#[derive(Clone, Debug, Default)]
+pub struct Generation(Arc<AtomicU64>);
+
+impl Generation {
+    /// Move on from the work underway, returning the generation after it
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, atomic::Ordering::SeqCst) + 1
+    }
+
+    pub fn current(&self) -> u64 {
+        self.0.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Whether nothing has moved on since `generation`
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.current() == generation
+    }
+}
+
+/// Drop the results past the best `max`, so memory and sorting stay
+/// bounded however much gets listed. Whether any were dropped
+pub fn cap_results(output: &mut Vec<OutputLine>, max: Option<usize>) -> bool {
+    match max.filter(|&max| output.len() > max) {
+        Some(max) => {
+            output.truncate(max);
+            true
+        }
+        None => false,
+    }
+}
+
+/// What a query run without the UI comes to
+#[derive(Debug, PartialEq)]
+pub enum Pick<'a> {
+    /// the best of the results
+    Best(&'a OutputLine),
+    /// nothing matched
+    Nothing,
+    /// this many matched, where only one would do
+    Ambiguous(usize),
+}
+
+/// The best of the sorted `output`, unless there's none, or more than one
+/// when `unique` asks for a single answer
+pub fn pick_one(output: &[OutputLine], unique: bool) -> Pick<'_> {
+    match output {
+        [] => Pick::Nothing,
+        [_, _, ..] if unique => Pick::Ambiguous(output.len()),
+        [best, ..] => Pick::Best(best),
+    }
+}
+
+/// The result selected after moving `by` from `selected`, among `len`
+/// of them, stopping at either end, unless `cycle` brings a single step
+/// past one round to the other
+pub fn move_selection(selected: usize, by: i32, len: usize, cycle: bool) -> usize {
+    let last = len.saturating_sub(1);
+    match by {
+        1 if cycle && selected == last => 0,
+        -1 if cycle && selected == 0 => last,
+        _ => (selected as i64 + i64::from(by)).clamp(0, last as i64) as usize,
+    }
+}
+
+/// The selection kept to one of the `len` results, and the first one
+/// shown scrolled just enough that it's among the `rows` drawn, as
+/// `(selected, scroll)`
+pub fn keep_in_view(selected: usize, scroll: usize, len: usize, rows: usize) -> (usize, usize) {
+    let selected = selected.min(len.saturating_sub(1));
+    let mut scroll = scroll.min(selected);
+    if rows > 0 && selected >= scroll + rows {
+        scroll = selected + 1 - rows;
+    }
+    (selected, scroll)
+}
+
+/// Move the results pinned with Alt-p to the top, keeping their order
+/// and that of everything else
+pub fn pin_to_top(output: &mut [OutputLine], dir: &Path, pinned: &[PathBuf]) {
+    if !pinned.is_empty() {
+        output.sort_by_cached_key(|line| !pinned.contains(&dir.join(&line.data)));
+    }
+}
+
+/// Order `output` by name when a one-off name sort is active and in
+/// `sort` order for `pattern` otherwise
+pub fn sort_output(
+    output: &mut [OutputLine],
+    name_sort: Option<NameSort>,
+    sort: SortMode,
+    pattern: &str,
+) {
+    match name_sort {
+        Some(NameSort::Ascending) => output.sort_by(|a, b| a.data.cmp(&b.data)),
+        Some(NameSort::Descending) => output.sort_by(|a, b| b.data.cmp(&a.data)),
+        None => output.sort_by(|a, b| sort.cmp(pattern, a, b)),
+    }
+}
+
+/// Find the deepest directory under `dir` that holds every one of `paths`,
+/// returning `None` when that is just `dir` itself
+pub fn common_dir<'a>(dir: &Path, paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+
+    for path in paths {
+        common = Some(match common {
+            None => path.to_path_buf(),
+            Some(prefix) => prefix
+                .components()
+                .zip(path.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+
+    // a lone file match zooms into the directory holding it
+    let mut common = dir.join(common?);
+    while !common.is_dir() {
+        if !common.pop() {
+            return None;
+        }
+    }
+
+    if common == dir {
+        None
+    } else {
+        Some(common)
+    }
+}
+
+/// The directories visited, like a browser's history, for Alt-Left and
+/// Alt-Right to step back and forward through
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    pub dirs: Vec<PathBuf>,
+    /// where in `dirs` we are
+    pub at: usize,
+    /// where the step being taken goes, until the move there is settled
+    step: Option<usize>,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> History {
+        History {
+            dirs: vec![dir],
+            at: 0,
+            step: None,
+        }
+    }
+
+    /// Take a step back or forward, the directory it goes to, if there's
+    /// one that way. It only counts once the move is settled
+    pub fn step(&mut self, back: bool) -> Option<PathBuf> {
+        let to = if back {
+            self.at.checked_sub(1)
+        } else {
+            Some(self.at + 1).filter(|&to| to < self.dirs.len())
+        }?;
+
+        self.step = Some(to);
+        Some(self.dirs[to].clone())
+    }
+
+    /// Arrive in `dir`, where a step lands where it was going and any
+    /// other move drops whatever was forward of here
+    pub fn arrive(&mut self, dir: &Path) {
+        match self.step.take() {
+            Some(to) => self.at = to,
+            None if self.dirs[self.at] != dir => {
+                self.dirs.truncate(self.at + 1);
+                self.dirs.push(dir.to_path_buf());
+                self.at += 1;
+            }
+            None => {}
+        }
+    }
+}
+
+/// Settle a move to `to`, which `denied` says can't be read. The keys
+/// that move clear the query and step through `history` before the move
+/// is tried, so a move that can't happen puts back the query `typed`
+/// before it and stays where it was. Whether the move goes ahead
+pub fn settle_move(
+    denied: bool,
+    to: &Path,
+    typed: &str,
+    query: &mut String,
+    history: &mut History,
+) -> bool {
+    if denied {
+        history.step = None;
+        *query = typed.to_string();
+        return false;
+    }
+
+    history.arrive(to);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(width: usize) -> DisplayOptions<'static> {
+        DisplayOptions {
+            width,
+            dim: 0,
+            home: None,
+            max_runs: 64,
+            pattern: "",
+            case_shade: None,
+            ellipsis: Ellipsis::End,
+            theme: Theme::default(),
+            base: None,
+            color: true,
+        }
+    }
+
+    /// A line drawn with the chars at `indices` matched
+    fn highlighted(data: &str, indices: &[usize]) -> OutputLine {
+        OutputLine {
+            data: data.to_string(),
+            match_str: None,
+            match_offset: 0,
+            score: 0,
+            indices: indices.to_vec(),
+            matched: true,
+            below_min_score: false,
+            highlighted: true,
+        }
+    }
+
+    /// `text` without its escapes, as it shows on the terminal
+    fn visible(text: &str) -> String {
+        let mut shown = String::new();
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\x1b' {
+                chars.by_ref().find(|ch| ch.is_ascii_alphabetic());
+            } else {
+                shown.push(ch);
+            }
+        }
+        shown
+    }
+
+    fn reset() -> String {
+        Shade::Plain.escape(&Theme::default(), None)
+    }
+
+    /// The chars of `text` drawn in the match color
+    fn matched_chars(text: &str) -> String {
+        let matched = Shade::Match.escape(&Theme::default(), None);
+        let mut found = String::new();
+        let mut in_match = false;
+        let mut rest = text;
+        while let Some(ch) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix(matched.as_str()) {
+                in_match = true;
+                rest = after;
+            } else if ch == '\x1b' {
+                in_match = false;
+                let end = rest.find(|ch: char| ch.is_ascii_alphabetic()).unwrap_or(0);
+                rest = &rest[end + 1..];
+            } else {
+                if in_match {
+                    found.push(ch);
+                }
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn match_on_the_last_column_ends_on_a_reset() {
+        let drawn = highlighted("abcd", &[3]).display(&options(4));
+
+        assert_eq!(visible(&drawn), "abcd");
+        assert!(drawn.ends_with(&reset()));
+    }
+
+    #[test]
+    fn match_just_before_the_cut_ends_on_a_reset() {
+        let drawn = highlighted("abcdefgh", &[3]).display(&options(5));
+
+        assert_eq!(visible(&drawn), "abcd…");
+        assert!(drawn.ends_with(&reset()));
+    }
+
+    #[test]
+    fn match_at_the_cut_ends_on_a_reset() {
+        let drawn = highlighted("abcdefgh", &[4]).display(&options(5));
+
+        assert_eq!(visible(&drawn).chars().count(), 5);
+        assert!(visible(&drawn).contains('e'));
+        assert!(drawn.ends_with(&reset()));
+    }
+
+    #[test]
+    fn color_switches_are_capped() {
+        // every other char matched, for a switch of color at every char
+        let data = "ab".repeat(200);
+        let every_other: Vec<usize> = (0..data.len()).step_by(2).collect();
+        let line = highlighted(&data, &every_other);
+        let options = DisplayOptions {
+            max_runs: 8,
+            ..options(data.len())
+        };
+
+        let drawn = line.display(&options);
+        let longest = Shade::Match
+            .escape(&options.theme, None)
+            .len()
+            .max(reset().len());
+
+        assert_eq!(visible(&drawn), data);
+        assert!(drawn.len() <= data.len() + (options.max_runs + 1) * longest);
+        assert!(drawn.ends_with(&reset()));
+    }
+
+    #[test]
+    fn all_matched_line_is_one_run() {
+        let data = "x".repeat(300);
+        let all: Vec<usize> = (0..data.len()).collect();
+        let matched = Shade::Match.escape(&Theme::default(), None);
+
+        let drawn = highlighted(&data, &all).display(&options(data.len()));
+
+        assert_eq!(drawn, format!("{}{}{}", matched, data, reset()));
+    }
+
+    #[test]
+    fn each_run_gets_one_escape() {
+        let matched = Shade::Match.escape(&Theme::default(), None);
+
+        // five runs, two of them matched, then the reset every line ends on
+        let drawn = highlighted("abcdefgh", &[1, 2, 3, 6]).display(&options(8));
+
+        assert_eq!(drawn.matches(&matched).count(), 2);
+        assert_eq!(drawn.matches(&reset()).count(), 4);
+        assert_eq!(
+            drawn,
+            format!("{r}a{m}bcd{r}ef{m}g{r}h{r}", m = matched, r = reset())
+        );
+    }
+
+    #[test]
+    fn control_chars_are_drawn_as_placeholders() {
+        let data = "a\rb\tc\0d";
+        let line = highlighted(data, &[]);
+
+        let drawn = line.display(&options(20));
+
+        assert_eq!(visible(&drawn), "a\u{fffd}b\u{fffd}c\u{fffd}d");
+        assert!(!drawn.contains(['\r', '\t', '\0']));
+        // what's picked is still the name as it is
+        assert_eq!(line.data, data);
+    }
+
+    #[test]
+    fn control_chars_keep_their_highlight() {
+        let matched = Shade::Match.escape(&Theme::default(), None);
+
+        let drawn = highlighted("a\tb", &[1]).display(&options(20));
+
+        assert_eq!(matched_chars(&drawn), "\u{fffd}");
+        assert!(drawn.contains(&format!("{}\u{fffd}", matched)));
+    }
+
+    fn skim() -> Matcher {
+        Matcher {
+            scorer: Box::new(SkimMatcherV2::default()),
+            min_score: 1,
+        }
+    }
+
+    #[test]
+    fn highlight_lands_on_the_multibyte_chars_matched() {
+        let matcher = skim();
+        let mut line = OutputLine::new("café/ñ.txt".into(), Transform::Full, false, &matcher, "éñ");
+        let (_, indices) = SkimMatcherV2::default()
+            .fuzzy_indices("café/ñ.txt", "éñ")
+            .unwrap();
+
+        line.highlight(&matcher, "éñ");
+        let drawn = line.display(&DisplayOptions {
+            pattern: "éñ",
+            ..options(20)
+        });
+
+        assert_eq!(line.indices, indices);
+        assert_eq!(line.indices, vec![3, 5]);
+        assert_eq!(matched_chars(&drawn), "éñ");
+    }
+
+    #[test]
+    fn highlight_after_a_transform_counts_chars_too() {
+        let matcher = skim();
+        let mut line = OutputLine::new(
+            "日本/ñandú.txt".into(),
+            Transform::Basename,
+            false,
+            &matcher,
+            "dú",
+        );
+
+        line.highlight(&matcher, "dú");
+        let drawn = line.display(&DisplayOptions {
+            pattern: "dú",
+            ..options(20)
+        });
+
+        assert_eq!(matched_chars(&drawn), "dú");
+    }
+
+    fn lines(data: &[&str], matcher: &Matcher, pattern: &str) -> Vec<OutputLine> {
+        data.iter()
+            .map(|data| OutputLine::new(data.to_string(), Transform::Full, false, matcher, pattern))
+            .collect()
+    }
+
+    fn names(lines: &[OutputLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.data.as_str()).collect()
+    }
+
+    #[test]
+    fn exact_match_outscores_a_loose_one() {
+        let matcher = skim();
+
+        let exact = OutputLine::new("config".into(), Transform::Full, false, &matcher, "config");
+        let loose = OutputLine::new(
+            "cache/old/notes/fig.png".into(),
+            Transform::Full,
+            false,
+            &matcher,
+            "config",
+        );
+
+        assert!(exact.matched && loose.matched);
+        assert!(exact.score > loose.score);
+    }
+
+    #[test]
+    fn empty_pattern_leaves_the_default_score() {
+        let matcher = skim();
+
+        for line in lines(&["a", "src/main.rs", "日本"], &matcher, "") {
+            assert!(line.matched);
+            assert_eq!(line.score, i64::default());
+            assert!(line.indices.is_empty());
+        }
+    }
+
+    #[test]
+    fn update_rescores_for_the_new_pattern() {
+        let matcher = skim();
+        let mut line = OutputLine::new("src/lib.rs".into(), Transform::Full, false, &matcher, "");
+
+        line.update(&matcher, "lib");
+        assert!(line.matched);
+        assert!(line.score > 0);
+
+        line.update(&matcher, "xyz");
+        assert!(!line.matched);
+        assert_eq!(line.score, i64::default());
+        assert!(!line.highlighted);
+    }
+
+    #[test]
+    fn update_fuzz_sorts_best_first() {
+        let matcher = skim();
+        let mut output = lines(
+            &["m_a_i_n.c", "main.rs", "src/main.rs", "domain"],
+            &matcher,
+            "",
+        );
+        let mut unmatched = Vec::new();
+
+        update_fuzz(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "main",
+            SortMode::Score,
+            |_| false,
+        );
+
+        assert_eq!(output.len(), 4);
+        assert!(output.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(output[0].data, "main.rs");
+    }
+
+    #[test]
+    fn update_fuzz_with_an_empty_pattern_keeps_everything_by_name() {
+        let matcher = skim();
+        let mut output = lines(&["b", "c"], &matcher, "zzz");
+        let mut unmatched = lines(&["a"], &matcher, "zzz");
+
+        update_fuzz(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "",
+            SortMode::Auto,
+            |_| false,
+        );
+
+        assert_eq!(names(&output), ["a", "b", "c"]);
+        assert!(unmatched.is_empty());
+    }
+
+    #[test]
+    fn update_fuzz_sets_aside_what_doesnt_match() {
+        let matcher = skim();
+        let mut output = lines(&["alpha", "beta", "gamma"], &matcher, "");
+        let mut unmatched = Vec::new();
+
+        update_fuzz(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "qqq",
+            SortMode::Score,
+            |_| false,
+        );
+
+        assert!(output.is_empty());
+        assert_eq!(unmatched.len(), 3);
+        assert!(unmatched.iter().all(|line| !line.matched));
+    }
+
+    #[test]
+    fn bad_dir_reason_tells_the_errors_apart() {
+        use std::io::{Error, ErrorKind};
+
+        let denied = || Err(Error::from(ErrorKind::PermissionDenied));
+        let missing = || Err(Error::from(ErrorKind::NotFound));
+
+        assert_eq!(bad_dir_reason(Ok(false), false), "not a directory");
+        assert_eq!(bad_dir_reason(Ok(true), false), "can't open directory");
+        assert_eq!(bad_dir_reason(denied(), false), "permission denied");
+        // a link may be to somewhere that may not be looked into
+        assert_eq!(bad_dir_reason(denied(), true), "permission denied");
+        assert_eq!(bad_dir_reason(missing(), true), "broken link");
+        assert_eq!(bad_dir_reason(missing(), false), "no such directory");
+    }
+
+    #[test]
+    fn only_permission_errors_count_as_denied() {
+        use std::io::{Error, ErrorKind};
+
+        assert!(is_permission_denied::<()>(&Err(Error::from(
+            ErrorKind::PermissionDenied
+        ))));
+        assert!(!is_permission_denied::<()>(&Err(Error::from(
+            ErrorKind::NotFound
+        ))));
+        assert!(!is_permission_denied(&Ok(())));
+    }
+
+    #[test]
+    fn move_selection_stops_at_either_end() {
+        assert_eq!(move_selection(3, 1, 10, false), 4);
+        assert_eq!(move_selection(3, -1, 10, false), 2);
+        assert_eq!(move_selection(9, 1, 10, false), 9);
+        assert_eq!(move_selection(0, -1, 10, false), 0);
+        // a page at a time goes no further than the end
+        assert_eq!(move_selection(7, 5, 10, false), 9);
+        assert_eq!(move_selection(2, -5, 10, false), 0);
+        assert_eq!(move_selection(4, i32::MAX, 10, false), 9);
+        assert_eq!(move_selection(4, i32::MIN, 10, false), 0);
+        assert_eq!(move_selection(0, 1, 0, false), 0);
+    }
+
+    #[test]
+    fn cycle_wraps_a_step_past_either_end() {
+        assert_eq!(move_selection(9, 1, 10, true), 0);
+        assert_eq!(move_selection(0, -1, 10, true), 9);
+        assert_eq!(move_selection(4, 1, 10, true), 5);
+        // bigger moves still stop at the end
+        assert_eq!(move_selection(8, 5, 10, true), 9);
+        assert_eq!(move_selection(1, -5, 10, true), 0);
+        assert_eq!(move_selection(0, 1, 1, true), 0);
+    }
+
+    #[test]
+    fn cycle_brings_the_scroll_along() {
+        // from the bottom of a scrolled list round to the top
+        let (selected, scroll) = keep_in_view(move_selection(9, 1, 10, true), 5, 10, 5);
+        assert_eq!((selected, scroll), (0, 0));
+
+        // and from the top round to the bottom
+        let (selected, scroll) = keep_in_view(move_selection(0, -1, 10, true), 0, 10, 5);
+        assert_eq!((selected, scroll), (9, 5));
+    }
+
+    #[test]
+    fn keep_in_view_follows_the_selection() {
+        // moving below the last row drawn scrolls it just into view
+        assert_eq!(keep_in_view(5, 0, 10, 5), (5, 1));
+        // and above the first row scrolls back up to it
+        assert_eq!(keep_in_view(2, 4, 10, 5), (2, 2));
+        assert_eq!(keep_in_view(3, 1, 10, 5), (3, 1));
+    }
+
+    #[test]
+    fn keep_in_view_when_the_results_shrink() {
+        assert_eq!(keep_in_view(8, 6, 3, 5), (2, 2));
+        assert_eq!(keep_in_view(8, 6, 0, 5), (0, 0));
+        // with no rows to draw on, there's nothing to keep it in
+        assert_eq!(keep_in_view(8, 6, 10, 0), (8, 6));
+    }
+
+    #[test]
+    fn cancelled_listing_lets_no_more_lines_in() {
+        let listing = Cancel::default();
+        let reader = listing.clone();
+        let next = Cancel::default();
+
+        assert_eq!(reader.admit(Some("a")), Some("a"));
+        assert_eq!(reader.admit(None::<&str>), None);
+
+        // going into another directory gives up on the old listing
+        listing.cancel();
+        assert!(reader.is_cancelled());
+        assert_eq!(reader.admit(Some("b")), None);
+        // while the one after it has a token of its own
+        assert!(!next.is_cancelled());
+        assert_eq!(next.admit(Some("c")), Some("c"));
+    }
+
+    #[test]
+    fn generation_moves_on_for_every_clone() {
+        let latest = Generation::default();
+        let worker = latest.clone();
+
+        let first = latest.next();
+        assert!(worker.is_current(first));
+        // a newer pattern leaves the first one's work out of date
+        let second = latest.next();
+        assert!(!worker.is_current(first));
+        assert!(worker.is_current(second));
+    }
+
+    #[test]
+    fn pick_one_with_nothing_matching() {
+        let matcher = skim();
+        let output = lines(&["alpha", "beta"], &matcher, "qqq");
+        let (output, _): (Vec<_>, Vec<_>) = output.into_iter().partition(|line| line.matched);
+
+        assert_eq!(pick_one(&output, false), Pick::Nothing);
+        assert_eq!(pick_one(&output, true), Pick::Nothing);
+    }
+
+    #[test]
+    fn pick_one_with_a_single_match() {
+        let matcher = skim();
+        let output = lines(&["main.rs"], &matcher, "main");
+
+        assert_eq!(pick_one(&output, false), Pick::Best(&output[0]));
+        assert_eq!(pick_one(&output, true), Pick::Best(&output[0]));
+    }
+
+    #[test]
+    fn pick_one_with_several_takes_the_best_unless_one_is_wanted() {
+        let matcher = skim();
+        let output = lines(&["main.rs", "src/main.rs"], &matcher, "main");
+
+        assert_eq!(pick_one(&output, false), Pick::Best(&output[0]));
+        assert_eq!(pick_one(&output, true), Pick::Ambiguous(2));
+    }
+
+    #[test]
+    fn cap_results_keeps_the_best() {
+        let matcher = skim();
+        let mut output = lines(&["a", "b", "c"], &matcher, "");
+
+        assert!(!cap_results(&mut output, None));
+        assert!(!cap_results(&mut output, Some(3)));
+        assert_eq!(output.len(), 3);
+
+        assert!(cap_results(&mut output, Some(2)));
+        assert_eq!(names(&output), ["a", "b"]);
+        assert!(cap_results(&mut output, Some(0)));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn update_fuzz_unless_gives_up_for_a_newer_pattern() {
+        let matcher = skim();
+        let data: Vec<String> = (0..3 * PARALLEL_SCORING_MIN)
+            .map(|n| format!("f{}", n))
+            .collect();
+        let data: Vec<&str> = data.iter().map(String::as_str).collect();
+        let mut output = lines(&data, &matcher, "");
+        let mut unmatched = Vec::new();
+        update_fuzz(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "f1",
+            SortMode::Score,
+            |_| false,
+        );
+        let before: Vec<(String, bool)> = output
+            .iter()
+            .chain(&unmatched)
+            .map(|line| (line.data.clone(), line.matched))
+            .collect();
+
+        // "f12" is typed while "f11" is still being matched
+        let checks = std::sync::atomic::AtomicUsize::new(0);
+        let finished = update_fuzz_unless(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "f11",
+            SortMode::Score,
+            |_| false,
+            || checks.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0,
+        );
+        assert!(!finished);
+        // in the same order and matched the same way as before
+        let after: Vec<(String, bool)> = output
+            .iter()
+            .chain(&unmatched)
+            .map(|line| (line.data.clone(), line.matched))
+            .collect();
+        assert_eq!(after, before);
+        assert!(output.iter().all(|line| line.matched));
+
+        let finished = update_fuzz_unless(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "f12",
+            SortMode::Score,
+            |_| false,
+            || false,
+        );
+        assert!(finished);
+
+        let mut fresh = lines(&data, &matcher, "");
+        let mut rest = Vec::new();
+        update_fuzz(
+            &mut fresh,
+            &mut rest,
+            &matcher,
+            "f12",
+            SortMode::Score,
+            |_| false,
+        );
+        assert_eq!(names(&output), names(&fresh));
+        assert_eq!(unmatched.len(), rest.len());
+        assert!(output
+            .iter()
+            .zip(&fresh)
+            .all(|(line, want)| line.score == want.score && line.indices == want.indices));
+    }
+
+    /// A scorer giving every line the same score, whatever the query
+    struct Flat(i64);
+
+    impl Scorer for Flat {
+        fn score_indices(&self, _: &str, _: &str) -> Option<(i64, Vec<usize>)> {
+            Some((self.0, Vec::new()))
+        }
+    }
+
+    /// `path` with this platform's separator in place of each `/`
+    fn native(path: &str) -> String {
+        path.replace('/', std::path::MAIN_SEPARATOR_STR)
+    }
+
+    #[test]
+    fn shell_quote_leaves_nothing_to_expand() {
+        let quote = |path: &str| String::from_utf8(shell_quote(Path::new(path))).unwrap();
+
+        assert_eq!(quote("plain"), "'plain'");
+        assert_eq!(quote("a b$HOME*`x`"), "'a b$HOME*`x`'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("''"), r"''\'''\'''");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn cd_command_quotes_the_dir() {
+        assert_eq!(
+            cd_command(Path::new("/tmp/a b'c")),
+            b"cd -- '/tmp/a b'\\''c'\n"
+        );
+        // a name starting with a dash isn't taken for an option
+        assert_eq!(cd_command(Path::new("-x")), b"cd -- '-x'\n");
+    }
+
+    #[test]
+    fn relative_to_steps_down_and_up() {
+        let rel = |path: &str, base: &str| {
+            relative_to(Path::new(&native(path)), Path::new(&native(base)))
+        };
+
+        assert_eq!(rel("/a/b/c", "/a"), PathBuf::from(native("b/c")));
+        assert_eq!(rel("/a", "/a/b/c"), PathBuf::from(native("../..")));
+        assert_eq!(rel("/a/x/y", "/a/b"), PathBuf::from(native("../x/y")));
+        assert_eq!(rel("/a/b", "/a/b"), PathBuf::from("."));
+    }
+
+    #[test]
+    fn normalize_lexically_resolves_dots() {
+        let normal = |path: &str| normalize_lexically(Path::new(&native(path)));
+
+        assert_eq!(normal("/a/./b/../c"), PathBuf::from(native("/a/c")));
+        assert_eq!(normal("/a/b/.."), PathBuf::from(native("/a")));
+        // there's nothing above the root to step up to
+        assert_eq!(normal("/.."), PathBuf::from(native("/")));
+    }
+
+    #[test]
+    fn common_prefix_len_ends_on_a_separator() {
+        let len = |lines: &[&str]| {
+            let lines: Vec<String> = lines.iter().map(|line| native(line)).collect();
+            common_prefix_len(lines.iter().map(String::as_str))
+        };
+
+        assert_eq!(len(&[]), 0);
+        assert_eq!(len(&["src/lib.rs"]), 4);
+        assert_eq!(len(&["src/lib.rs", "src/main.rs"]), 4);
+        // a shared start of a name isn't a directory
+        assert_eq!(len(&["src/lib.rs", "srcs/x"]), 0);
+        assert_eq!(len(&["a/b/c", "a/b/d", "a/e"]), 2);
+        assert_eq!(len(&["é/a", "é/b"]), "é/".len());
+    }
+
+    #[test]
+    fn list_entry_follows_the_current_dir() {
+        let base = PathBuf::from(native("/repo"));
+        let entry =
+            |dir: &str, entry: &str| list_entry(&base, Path::new(&native(dir)), native(entry));
+
+        assert_eq!(entry("/repo", "src/lib.rs"), Some(native("src/lib.rs")));
+        assert_eq!(entry("/repo/src", "src/lib.rs"), Some("lib.rs".to_string()));
+        assert_eq!(entry("/repo/src", "benches/matching.rs"), None);
+        // the directory itself isn't one of its entries
+        assert_eq!(entry("/repo/src", "src"), None);
+        assert_eq!(entry("/", "src/lib.rs"), Some(native("repo/src/lib.rs")));
+    }
+
+    #[test]
+    fn basename_bonus_goes_by_the_file_name() {
+        let bonus = |line: &str, pattern| basename_bonus(&native(line), pattern);
+
+        assert_eq!(bonus("src/main.rs", "main"), BASENAME_EXACT_BONUS);
+        assert_eq!(bonus("src/Main.rs", "main.rs"), BASENAME_EXACT_BONUS);
+        assert_eq!(bonus("src/mainframe.c", "main"), BASENAME_PREFIX_BONUS);
+        assert_eq!(bonus("main/lib.rs", "main"), 0);
+        assert_eq!(bonus("src/", "src"), BASENAME_EXACT_BONUS);
+        assert_eq!(bonus("anything", ""), 0);
+    }
+
+    #[test]
+    fn basename_matches_rank_ahead_of_equal_raw_scores() {
+        let matcher = Matcher {
+            scorer: Box::new(Flat(10)),
+            min_score: 1,
+        };
+        let data = ["main/other.rs", "a/mainline.rs", "b/main.rs"].map(native);
+        let mut output = lines(&data.each_ref().map(String::as_str), &matcher, "");
+        let mut unmatched = Vec::new();
+
+        update_fuzz(
+            &mut output,
+            &mut unmatched,
+            &matcher,
+            "main",
+            SortMode::Score,
+            |_| false,
+        );
+
+        // without the bonus the shortest path would come first
+        assert_eq!(names(&output), [&data[2], &data[1], &data[0]]);
+    }
+
+    #[test]
+    fn basename_bonus_saturates_instead_of_overflowing() {
+        let matcher = Matcher {
+            scorer: Box::new(Flat(i64::MAX)),
+            min_score: 1,
+        };
+        let mut line = OutputLine::new("main.rs".into(), Transform::Full, false, &matcher, "main");
+        assert_eq!(line.score, i64::MAX);
+
+        line.update(&matcher, "main.rs");
+        assert_eq!(line.score, i64::MAX);
+    }
+
+    #[test]
+    fn ties_rank_the_shorter_path_then_by_name() {
+        let line = |data: &str, score| OutputLine {
+            score,
+            ..highlighted(data, &[])
+        };
+        let mut ranked = vec![line("bb", 5), line("aaa", 5), line("ab", 5), line("z", 9)];
+
+        ranked.sort_by(OutputLine::cmp_rank);
+
+        assert_eq!(names(&ranked), ["z", "ab", "bb", "aaa"]);
+    }
+
+    #[test]
+    fn set_aside_keeps_matches_and_what_keep_holds_on_to() {
+        let unmatched_line = |data: &str| OutputLine {
+            matched: false,
+            ..highlighted(data, &[])
+        };
+        let mut output = vec![
+            highlighted("hit", &[]),
+            unmatched_line("pinned"),
+            unmatched_line("miss"),
+        ];
+        let mut unmatched = vec![unmatched_line("older")];
+
+        set_aside_unmatched(&mut output, &mut unmatched, |line| line.data == "pinned");
+
+        assert_eq!(names(&output), ["hit", "pinned"]);
+        assert_eq!(names(&unmatched), ["older", "miss"]);
+    }
+
+    #[test]
+    fn tilde_expands_to_home() {
+        let home = home_dir().expect("tests need $HOME");
+
+        assert_eq!(expand_path("~"), home);
+        assert_eq!(expand_path("~/sub"), format!("{}/sub", home));
+        // only a leading ~ on its own is the home directory
+        assert_eq!(expand_path("~user/sub"), "~user/sub");
+        assert_eq!(expand_path("a/~"), "a/~");
+    }
+
+    #[test]
+    fn variables_expand_bare_and_braced() {
+        let path = std::env::var("PATH").expect("tests need $PATH");
+
+        assert_eq!(expand_path("$PATH/x"), format!("{}/x", path));
+        assert_eq!(expand_path("${PATH}x"), format!("{}x", path));
+    }
+
+    #[test]
+    fn undefined_variables_stay_as_typed() {
+        let unset = "FDI_TEST_SURELY_UNSET";
+        assert!(std::env::var(unset).is_err());
+
+        assert_eq!(
+            expand_path(&format!("${}/x", unset)),
+            format!("${}/x", unset)
+        );
+        assert_eq!(
+            expand_path(&format!("${{{}}}", unset)),
+            format!("${{{}}}", unset)
+        );
+        assert_eq!(expand_path("cost$"), "cost$");
+        assert_eq!(expand_path("${open"), "${open");
+    }
+
+    #[test]
+    fn backspace_removes_one_multibyte_char() {
+        assert_eq!(remove_char("café", 3), "caf");
+        assert_eq!(remove_char("日本語", 2), "日本");
+        assert_eq!(remove_char("é", 0), "");
+    }
+
+    #[test]
+    fn removing_mid_query_keeps_the_neighbours() {
+        assert_eq!(remove_char("a日b", 1), "ab");
+        assert_eq!(remove_char("ñandú", 0), "andú");
+    }
+
+    #[test]
+    fn removing_past_the_end_leaves_the_query() {
+        assert_eq!(remove_char("日", 1), "日");
+        assert_eq!(remove_char("", 0), "");
+    }
+
+    #[test]
+    fn accents_fold_to_their_base_letters() {
+        assert_eq!(fold_accents("café"), "cafe");
+        assert_eq!(fold_accents("Résumé.pdf"), "Resume.pdf");
+        assert_eq!(fold_accents("naïve/Ångström"), "naive/Angstrom");
+        assert_eq!(fold_accents("plain.txt"), "plain.txt");
+    }
+
+    #[test]
+    fn folding_keeps_one_char_per_char() {
+        // ß and æ don't come apart into a letter and marks, so they stay
+        for name in ["façade", "straße", "æther", "日本語"] {
+            assert_eq!(fold_accents(name).chars().count(), name.chars().count());
+        }
+        assert_eq!(fold_accents("straße"), "straße");
+    }
+
+    #[test]
+    fn ascii_query_matches_accented_name_when_folding() {
+        let matcher = skim();
+
+        let folded = OutputLine::new("café.txt".into(), Transform::Full, true, &matcher, "cafe");
+        let exact = OutputLine::new("café.txt".into(), Transform::Full, false, &matcher, "cafe");
+
+        assert!(folded.matched);
+        assert!(!exact.matched);
+        // what's shown is still the name as it is
+        assert_eq!(folded.data, "café.txt");
+    }
+
+    #[test]
+    fn folded_match_highlights_the_accented_char() {
+        let matcher = skim();
+        let mut line = OutputLine::new("café".into(), Transform::Full, true, &matcher, "cafe");
+
+        line.highlight(&matcher, "cafe");
+
+        assert_eq!(line.indices, vec![0, 1, 2, 3]);
+    }
+
+    /// A history of `/a` then `/b`, stepped back to `/a`
+    fn history_back_at_a() -> History {
+        let mut history = History::new(PathBuf::from("/a"));
+        history.arrive(Path::new("/b"));
+        assert_eq!(history.step(true), Some(PathBuf::from("/a")));
+        history
+    }
+
+    #[test]
+    fn denied_dir_keeps_query_and_history_position() {
+        let mut history = history_back_at_a();
+        // Alt-Left cleared the query on the way
+        let mut query = String::new();
+
+        let moved = settle_move(true, Path::new("/a"), "abc", &mut query, &mut history);
+
+        assert!(!moved);
+        assert_eq!(query, "abc");
+        assert_eq!(history.at, 1);
+        // and the refused step doesn't land on the next move either
+        history.arrive(Path::new("/c"));
+        assert_eq!(
+            history.dirs,
+            [Path::new("/a"), Path::new("/b"), Path::new("/c")]
+        );
+        assert_eq!(history.at, 2);
+    }
+
+    #[test]
+    fn readable_dir_settles_the_step() {
+        let mut history = history_back_at_a();
+        let mut query = String::new();
+
+        let moved = settle_move(false, Path::new("/a"), "abc", &mut query, &mut history);
+
+        assert!(moved);
+        assert_eq!(query, "");
+        assert_eq!(history.at, 0);
+        assert_eq!(history.dirs.len(), 2);
+    }
+
+    #[test]
+    fn new_visit_drops_what_was_forward() {
+        let mut history = history_back_at_a();
+        history.arrive(Path::new("/a"));
+
+        history.arrive(Path::new("/c"));
+
+        assert_eq!(history.dirs, [Path::new("/a"), Path::new("/c")]);
+        assert_eq!(history.at, 1);
+        assert_eq!(history.step(false), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn strip_verbatim_drops_the_prefix_from_drive_paths() {
+        assert_eq!(
+            strip_verbatim(PathBuf::from(r"\\?\C:\Users\me")),
+            PathBuf::from(r"C:\Users\me")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn strip_verbatim_turns_verbatim_unc_into_a_share() {
+        assert_eq!(
+            strip_verbatim(PathBuf::from(r"\\?\UNC\server\share\dir")),
+            PathBuf::from(r"\\server\share\dir")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn strip_verbatim_keeps_what_needs_the_prefix() {
+        // a volume GUID means nothing without it, and a plain path has none
+        for path in [
+            r"\\?\Volume{0a1b2c3d}\dir",
+            r"C:\Users\me",
+            r"\\server\share",
+        ] {
+            assert_eq!(strip_verbatim(PathBuf::from(path)), PathBuf::from(path));
+        }
+    }
+}
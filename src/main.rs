@@ -1,263 +1,6269 @@
 /// Trying to make an interactive version of fd
 /// much like fzf but with the specific purpose to navigate
 /// the filesystem
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::io::{stdout, StdoutLock, Write};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
-use std::time::Duration;
+use std::process::{ExitCode, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crossterm::event::{
+    DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    MouseButton, MouseEvent, MouseEventKind,
+};
+use crossterm::style::{Attribute, Color, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::ExecutableCommand;
+#[cfg(feature = "nucleo")]
+use fdi::Nucleo;
+use fdi::{
+    abbreviate_home, bad_dir_reason, byte_offset, canonicalize, cap_results, cd_command,
+    common_dir, common_prefix_len, delete_word, expand_path, fold_accents, grep_hit, home_dir,
+    human_age, human_size, is_dir_itself, is_permission_denied, keep_in_view, list_entry,
+    move_selection, normalize_lexically, pick_one, pin_to_top, relative_to, remove_char,
+    render_prompt, set_aside_unmatched, settle_move, shell_quote, sort_output, update_fuzz,
+    update_fuzz_unless, Cancel, DisplayOptions, Ellipsis, Generation, History, LsColors, Matcher,
+    NameSort, OutputLine, Pick, Scorer, SortMode, Substring, Theme, Transform,
+};
 use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
-use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::{IntoRawMode, RawTerminal};
-use tokio::io::{AsyncBufReadExt, BufReader, Lines};
-use tokio::process::{ChildStdout, Command};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, Lines};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot};
 
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
-struct OutputLine {
-    data: String,
-    score: i64,
-    indices: Vec<usize>,
+/// Options controlling how fdi behaves, parsed from the command line
+#[derive(Debug)]
+struct Config {
+    /// keep the typed query when zooming into the common directory
+    /// of the matches instead of clearing it
+    zoom_keep_query: bool,
+    /// keep the typed query when moving to another directory, other than
+    /// by typing its path, so it goes on filtering the new listing
+    keep_query: bool,
+    /// draw directories with a separator after them when the listing
+    /// doesn't end them with one, like a --from-file list
+    dir_slash: bool,
+    /// navigate by the logical path, keeping symlinks in the prompt like
+    /// `cd -L`, instead of canonicalizing. Canonical paths (the default)
+    /// always show where you really are, but descending through a symlink
+    /// jumps to its target and `..` from there goes to the target's parent
+    logical: bool,
+    /// read the candidate paths from this newline separated list instead
+    /// of running fd, e.g. the output of `git ls-files`. `-` is stdin,
+    /// which is also what's read when something is piped into fdi
+    from_file: Option<PathBuf>,
+    /// the lines piped in on stdin, once reading them has started
+    piped: Option<Piped>,
+    /// the directory to start in instead of the current one, given with
+    /// `--start-dir` or as a plain argument like `fdi ~/code`
+    start: Option<PathBuf>,
+    /// frame the results region with box drawing characters
+    border: BorderStyle,
+    /// columns of space between the results and the sides of the region
+    padding: u16,
+    /// dim the leading directories the visible results all share, so what
+    /// differs between them stands out
+    dim_prefix: bool,
+    /// show the home directory in full instead of abbreviating it to `~`
+    full_home: bool,
+    /// the whitespace to trim from each line before it's matched
+    trim: Trim,
+    /// the part of each line the query is matched against
+    transform: Transform,
+    /// the most color switches drawn in a single result line
+    max_color_runs: usize,
+    /// what descending falls back to when the query isn't a directory
+    on_bad_dir: Vec<BadDir>,
+    /// where the prompt and status lines go
+    header: HeaderPosition,
+    /// start out showing the preview pane, toggled with Ctrl-p
+    preview: bool,
+    /// which side of the results the preview pane goes on
+    preview_position: PreviewPosition,
+    /// the order results start out in, switched with Alt-S
+    sort: SortMode,
+    /// draw chars that only matched ignoring case in this color, off by
+    /// default so every match is the same red
+    case_shade: Option<u8>,
+    /// whether to draw in color, by default unless NO_COLOR is set or the
+    /// terminal is a dumb one
+    color: ColorMode,
+    /// when the query's case has to match
+    case: CaseMode,
+    /// which fuzzy matching algorithm scores the results
+    algo: Algo,
+    /// the lowest fuzzy score that still counts as a match
+    min_score: i64,
+    /// say how many lines only missed the query by scoring under it
+    show_below: bool,
+    /// where results too long to fit are cut short with `…`
+    ellipsis: Ellipsis,
+    /// how many more times to run fd when it fails before listing
+    /// anything, for flaky network filesystems
+    fd_retries: u32,
+    /// how long to wait for the directory to stop changing before
+    /// listing it, so flying up with Backspace doesn't run fd on every
+    /// directory passed through
+    dir_settle: Duration,
+    /// the directories Alt plus a key jumps straight to, Alt-h for home
+    /// and Alt-r for the root unless --jump maps those keys elsewhere
+    jumps: Vec<(char, PathBuf)>,
+    /// where to write the directories visited once fdi exits, one per
+    /// line, with `-` for stderr
+    trace: Option<PathBuf>,
+    /// below this many columns only the query is drawn, with a hint to
+    /// widen the window
+    min_width: u16,
+    /// match accented chars by their base letter, so `cafe` finds `café`
+    fold_accents: bool,
+    /// keep an entry naming the directory being listed, like the `.`
+    /// some fd flags add, instead of dropping it
+    keep_root: bool,
+    /// the key that, with Alt, renames the selected result
+    rename_key: char,
+    /// how long a listing can take before the status line says it's
+    /// still loading, so quick listings never flash it
+    loading_grace: Duration,
+    /// how many levels deep fd lists to start with, all of them if unset
+    depth: Option<u32>,
+    /// let Alt-x run the selected result when it's an executable file
+    allow_exec: bool,
+    /// the arguments Alt-x runs the selected result with, where `{}` stands
+    /// for its path
+    exec_args: Vec<String>,
+    /// let Alt-w switch between cutting long results off and wrapping
+    /// them onto more rows
+    allow_wrap: bool,
+    /// a named pipe or file to write the top results to whenever the
+    /// query settles, for other tools to follow
+    mirror: Option<PathBuf>,
+    /// how many of the top results go to the mirror
+    mirror_count: usize,
+    /// the most results to keep, however many fit on screen, dropping the
+    /// rest so memory stays bounded on huge trees
+    max_results: Option<usize>,
+    /// let Right descend before any result has been listed, which it
+    /// otherwise ignores
+    early_enter: bool,
+    /// the query to start out with, or to match without the UI under
+    /// --select-1 and --count
+    query: Option<String>,
+    /// print the best match for --query and exit without opening the UI
+    select_1: bool,
+    /// print how many results --query matches out of how many are listed
+    /// and exit without opening the UI
+    count: bool,
+    /// with --select-1, fail when more than one result matches instead of
+    /// picking the best of them
+    exit_0: bool,
+    /// a file to write diagnostics to, as nothing can go to stderr while
+    /// the UI is drawn
+    log_file: Option<PathBuf>,
+    /// a named pipe or file to read commands like `cd DIR` from while the
+    /// UI is open, for other tools to steer fdi with, which --control-fd
+    /// sets to an inherited descriptor
+    control: Option<PathBuf>,
+    /// take clicks and the scroll wheel, which keeps the terminal from
+    /// selecting text with the mouse while fdi is open
+    mouse: bool,
+    /// start in vim's normal mode, where letters move around instead of
+    /// typing, also set by `vim = true` in config.toml
+    vim: bool,
+    /// step from the last result round to the first and back, rather than
+    /// stopping there, also set by `cycle = true` in config.toml
+    cycle: bool,
+    /// drop lines the listing already had, keeping the first of each
+    dedup: bool,
+    /// the kind of entries to start out listing, only directories with
+    /// --dirs-only for using fdi to cd around
+    types: EntryType,
+    /// how results are drawn, relative like fd lists them by default
+    display_paths: PathStyle,
+    /// how accepted paths are printed, absolute by default so scripts
+    /// get the same path wherever they're run from
+    print_paths: PathStyle,
+    /// start out having fd follow symlinks into the directories they
+    /// point at, toggled with Alt-F
+    follow: bool,
+    /// start out showing each result's size and age, toggled with Alt-l
+    metadata: bool,
+    /// show how many results are marked and what their files add up to
+    marked_size: bool,
+    /// a file to print the picked paths to instead of stdout
+    output: Option<PathBuf>,
+    /// turn a picked directory into a `cd` command for the shell to run,
+    /// printing any other picks as usual
+    cd: bool,
+    /// the file descriptor the `cd` command is written to, stdout if unset
+    cd_fd: Option<i32>,
+    /// end the printed path with a NUL instead of a newline, for
+    /// `xargs -0` and paths that have newlines in them
+    print0: bool,
+    /// single quote the printed paths for a shell to `eval`
+    shell_quote: bool,
+    /// the fd to run, e.g. `fdfind` where it's installed under that name,
+    /// from `$FDI_FD_BIN` unless given with `--fd-bin`
+    fd_bin: String,
+    /// arguments fd always gets, ahead of any entered with Alt-a
+    fd_args: Vec<String>,
+    /// a command to list each directory with instead of fd, run in the
+    /// directory with `{}` in its arguments standing for it, from
+    /// `$FDI_SOURCE_CMD` unless given with `--source-cmd`
+    source_cmd: Option<Vec<String>>,
+    /// print the usage and exit
+    help: bool,
 }
 
-impl OutputLine {
-    pub fn new(data: String, matcher: &SkimMatcherV2, match_with: &str) -> OutputLine {
-        let mut score: i64 = Default::default();
-        let mut indices: Vec<usize> = Default::default();
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            zoom_keep_query: false,
+            keep_query: false,
+            dir_slash: false,
+            logical: false,
+            from_file: None,
+            piped: None,
+            start: None,
+            border: BorderStyle::default(),
+            padding: 0,
+            dim_prefix: false,
+            full_home: false,
+            trim: Trim::default(),
+            transform: Transform::default(),
+            max_color_runs: 64,
+            on_bad_dir: vec![BadDir::Result, BadDir::Message],
+            header: HeaderPosition::default(),
+            preview: false,
+            preview_position: PreviewPosition::default(),
+            sort: SortMode::default(),
+            case_shade: None,
+            color: ColorMode::default(),
+            case: CaseMode::default(),
+            algo: Algo::default(),
+            // only the matches with so many gaps that they score below
+            // nothing, a couple of chars at opposite ends of a long path
+            min_score: 1,
+            show_below: false,
+            ellipsis: Ellipsis::default(),
+            fd_retries: 0,
+            dir_settle: Duration::from_millis(80),
+            jumps: home_dir()
+                .map(|home| ('h', PathBuf::from(home)))
+                .into_iter()
+                .chain(Some(('r', PathBuf::from("/"))))
+                .collect(),
+            trace: None,
+            min_width: 20,
+            fold_accents: false,
+            keep_root: false,
+            rename_key: 'm',
+            loading_grace: Duration::from_millis(100),
+            depth: None,
+            allow_exec: false,
+            exec_args: Vec::new(),
+            allow_wrap: false,
+            mirror: None,
+            mirror_count: 10,
+            max_results: None,
+            early_enter: false,
+            query: None,
+            select_1: false,
+            count: false,
+            exit_0: false,
+            log_file: None,
+            control: None,
+            mouse: true,
+            vim: false,
+            cycle: false,
+            dedup: false,
+            types: EntryType::default(),
+            display_paths: PathStyle::Relative,
+            print_paths: PathStyle::Absolute,
+            follow: false,
+            metadata: false,
+            marked_size: false,
+            output: None,
+            cd: false,
+            cd_fd: None,
+            print0: false,
+            shell_quote: false,
+            fd_bin: std::env::var("FDI_FD_BIN")
+                .ok()
+                .filter(|bin| !bin.is_empty())
+                .unwrap_or_else(|| "fd".to_string()),
+            fd_args: Vec::new(),
+            source_cmd: None,
+            help: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => config.help = true,
+                "--zoom-keep-query" => config.zoom_keep_query = true,
+                "--keep-query" => config.keep_query = true,
+                "--dir-slash" => config.dir_slash = true,
+                "--logical" => config.logical = true,
+                "--physical" => config.logical = false,
+                "--layout" => {
+                    let preset = args.next().ok_or("--layout needs a preset")?;
+                    LayoutPreset::parse(&preset)?.apply(&mut config);
+                }
+                "--sort" => {
+                    let mode = args.next().ok_or("--sort needs a mode")?;
+                    config.sort = SortMode::parse(&mode)?;
+                }
+                "--header" => {
+                    let position = args.next().ok_or("--header needs a position")?;
+                    config.header = HeaderPosition::parse(&position)?;
+                }
+                "--preview" => config.preview = true,
+                "--preview-position" => {
+                    let position = args.next().ok_or("--preview-position needs a position")?;
+                    config.preview_position = PreviewPosition::parse(&position)?;
+                }
+                "--border" => {
+                    let style = args.next().ok_or("--border needs a style")?;
+                    config.border = BorderStyle::parse(&style)?;
+                }
+                "--padding" => {
+                    let padding = args.next().ok_or("--padding needs a number of columns")?;
+                    config.padding = padding.parse()?;
+                }
+                "--dim-prefix" => config.dim_prefix = true,
+                "--fold-accents" => config.fold_accents = true,
+                "--keep-root" => config.keep_root = true,
+                "--dedup" => config.dedup = true,
+                "--dirs-only" => config.types = EntryType::Dirs,
+                "--type" => {
+                    let types = args.next().ok_or("--type needs a kind of entry")?;
+                    config.types = EntryType::parse(&types)?;
+                }
+                "--follow" => config.follow = true,
+                "--metadata" => config.metadata = true,
+                "--marked-size" => config.marked_size = true,
+                "--print0" => config.print0 = true,
+                "--shell-quote" => config.shell_quote = true,
+                "--output" => {
+                    let path = args.next().ok_or("--output needs a file")?;
+                    config.output = Some(PathBuf::from(path));
+                }
+                "--cd" => config.cd = true,
+                "--cd-fd" => {
+                    let fd = args.next().ok_or("--cd-fd needs a file descriptor")?;
+                    let fd =
+                        fd.parse().ok().filter(|&fd| fd >= 0).ok_or_else(|| {
+                            format!("--cd-fd needs a file descriptor, not {}", fd)
+                        })?;
+                    config.cd_fd = Some(fd);
+                    config.cd = true;
+                }
+                "--allow-wrap" => config.allow_wrap = true,
+                "--early-enter" => config.early_enter = true,
+                "--vim" => config.vim = true,
+                "--cycle" => config.cycle = true,
+                "--no-mouse" => config.mouse = false,
+                "--query" => {
+                    config.query = Some(args.next().ok_or("--query needs a query")?);
+                }
+                "--select-1" => config.select_1 = true,
+                "--count" => config.count = true,
+                "--exit-0" => config.exit_0 = true,
+                "--full-home" => config.full_home = true,
+                "--transform" => {
+                    let transform = args.next().ok_or("--transform needs a transform")?;
+                    config.transform = Transform::parse(&transform)?;
+                }
+                "--max-color-runs" => {
+                    let runs = args.next().ok_or("--max-color-runs needs a number")?;
+                    config.max_color_runs = runs.parse()?;
+                }
+                "--on-bad-dir" => {
+                    let steps = args
+                        .next()
+                        .ok_or("--on-bad-dir needs a list of fallbacks")?;
+                    config.on_bad_dir = steps
+                        .split(',')
+                        .map(BadDir::parse)
+                        .collect::<Result<_, _>>()?;
+                }
+                "--case-shade" => {
+                    let color = args.next().ok_or("--case-shade needs a color")?;
+                    config.case_shade = Some(parse_color(&color)?);
+                }
+                "--color" => {
+                    let when = args.next().ok_or("--color needs auto, always or never")?;
+                    config.color = ColorMode::parse(&when)?;
+                }
+                "--show-below" => config.show_below = true,
+                "--min-score" => {
+                    let score = args.next().ok_or("--min-score needs a number")?;
+                    config.min_score = score.parse()?;
+                }
+                "--display-paths" => {
+                    let style = args.next().ok_or("--display-paths needs a style")?;
+                    config.display_paths = PathStyle::parse(&style)?;
+                }
+                "--print-paths" => {
+                    let style = args.next().ok_or("--print-paths needs a style")?;
+                    config.print_paths = PathStyle::parse(&style)?;
+                }
+                "--algo" => {
+                    let algo = args.next().ok_or("--algo needs an algorithm")?;
+                    config.algo = Algo::parse(&algo)?;
+                }
+                "--case" => {
+                    let case = args.next().ok_or("--case needs a mode")?;
+                    config.case = CaseMode::parse(&case)?;
+                }
+                "--ellipsis" => {
+                    let ellipsis = args.next().ok_or("--ellipsis needs a position")?;
+                    config.ellipsis = Ellipsis::parse(&ellipsis)?;
+                }
+                "--fd-retries" => {
+                    let retries = args.next().ok_or("--fd-retries needs a number")?;
+                    config.fd_retries = retries.parse()?;
+                }
+                "--dir-settle" => {
+                    let millis = args.next().ok_or("--dir-settle needs milliseconds")?;
+                    config.dir_settle = Duration::from_millis(millis.parse()?);
+                }
+                "--jump" => {
+                    let jump = args.next().ok_or("--jump needs KEY=PATH")?;
+                    let (key, path) = parse_jump(&jump)?;
+                    config.jumps.retain(|(k, _)| *k != key);
+                    if let Some(path) = path {
+                        config.jumps.push((key, path));
+                    }
+                }
+                "--trace" => {
+                    let trace = args.next().ok_or("--trace needs a file, or - for stderr")?;
+                    config.trace = Some(PathBuf::from(trace));
+                }
+                "--min-width" => {
+                    let width = args.next().ok_or("--min-width needs a number of columns")?;
+                    config.min_width = width.parse()?;
+                }
+                "--rename-key" => {
+                    let key = args.next().ok_or("--rename-key needs a key")?;
+                    config.rename_key =
+                        parse_key(&key).map_err(|err| format!("--rename-key {}", err))?;
+                }
+                "--loading-grace" => {
+                    let millis = args.next().ok_or("--loading-grace needs milliseconds")?;
+                    config.loading_grace = Duration::from_millis(millis.parse()?);
+                }
+                "--depth" => {
+                    let depth = args.next().ok_or("--depth needs a number of levels")?;
+                    config.depth = Some(depth.parse()?).filter(|&depth| depth > 0);
+                }
+                "--allow-exec" => config.allow_exec = true,
+                "--exec-args" => {
+                    let exec_args = args.next().ok_or("--exec-args needs arguments")?;
+                    config.exec_args = shell_words::split(&exec_args)?;
+                }
+                "--mirror" => {
+                    let mirror = args.next().ok_or("--mirror needs a path")?;
+                    config.mirror = Some(PathBuf::from(mirror));
+                }
+                "--mirror-count" => {
+                    let count = args.next().ok_or("--mirror-count needs a number")?;
+                    config.mirror_count = count.parse()?;
+                }
+                "--control" => {
+                    let path = args.next().ok_or("--control needs a path")?;
+                    config.control = Some(PathBuf::from(path));
+                }
+                "--control-fd" => {
+                    let fd = args.next().ok_or("--control-fd needs a file descriptor")?;
+                    let fd: u32 = fd
+                        .parse()
+                        .map_err(|_| format!("--control-fd needs a file descriptor, not {}", fd))?;
+                    if !cfg!(unix) {
+                        return Err("--control-fd only works on unix".into());
+                    }
+                    config.control = Some(PathBuf::from(format!("/dev/fd/{}", fd)));
+                }
+                "--log-file" => {
+                    let path = args.next().ok_or("--log-file needs a path")?;
+                    config.log_file = Some(PathBuf::from(path));
+                }
+                "--max-results" => {
+                    let max = args.next().ok_or("--max-results needs a number")?;
+                    config.max_results = Some(max.parse()?);
+                }
+                "--fd-bin" => config.fd_bin = args.next().ok_or("--fd-bin needs a program")?,
+                "--source-cmd" => {
+                    let command = args.next().ok_or("--source-cmd needs a command")?;
+                    config.source_cmd = Some(parse_source_cmd(&command)?);
+                }
+                "--fd-args" => {
+                    let fd_args = args.next().ok_or("--fd-args needs arguments")?;
+                    config.fd_args = shell_words::split(&fd_args)?;
+                    check_fd_args(&config.fd_args)?;
+                }
+                // the rest goes to fd as it is, after what --fd-args gave
+                "--" => {
+                    config.fd_args.extend(args.by_ref());
+                    check_fd_args(&config.fd_args)?;
+                }
+                "--trim" => {
+                    let trim = args.next().ok_or("--trim needs a mode")?;
+                    config.trim = Trim::parse(&trim)?;
+                }
+                "--from-file" => {
+                    let list = args.next().ok_or("--from-file needs a path to a list")?;
+                    config.from_file = Some(PathBuf::from(list));
+                }
+                "--start-dir" => {
+                    let start = args.next().ok_or("--start-dir needs a directory")?;
+                    config.start = Some(PathBuf::from(start));
+                }
+                _ if !arg.starts_with('-') && config.start.is_none() => {
+                    config.start = Some(PathBuf::from(arg));
+                }
+                _ => return Err(format!("unknown argument: {}", arg).into()),
+            }
+        }
+
+        // the keys fdi handles itself come first, so a jump or the rename
+        // key on one of them would never be reached
+        if built_in_alt(config.rename_key, &config) {
+            return Err(format!("--rename-key {}: Alt-{0} is taken", config.rename_key).into());
+        }
+        for &(key, _) in &config.jumps {
+            if built_in_alt(key, &config) {
+                return Err(format!("--jump {}: Alt-{0} is taken", key).into());
+            }
+            if key == config.rename_key {
+                return Err(format!("--jump {}: Alt-{0} is the --rename-key", key).into());
+            }
+        }
+
+        // two ways of getting odd paths through to the shell, and quoted
+        // ones are still a path a line
+        if config.shell_quote && config.print0 {
+            return Err("--shell-quote and --print0 can't be used together".into());
+        }
+
+        if config.source_cmd.is_none() {
+            if let Some(command) = std::env::var("FDI_SOURCE_CMD")
+                .ok()
+                .filter(|c| !c.is_empty())
+            {
+                config.source_cmd = Some(
+                    parse_source_cmd(&command).map_err(|err| format!("FDI_SOURCE_CMD: {}", err))?,
+                );
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// What the results are matched against for `typed`, the query as
+    /// typed or with its accents folded to go with the folded results
+    pub fn query(&self, typed: &str) -> String {
+        if self.fold_accents {
+            fold_accents(typed)
+        } else {
+            typed.to_string()
+        }
+    }
+}
+
+/// Whether Alt with `key` does something of its own, ahead of jumps and
+/// renaming
+fn built_in_alt(key: char, config: &Config) -> bool {
+    ACTIONS.iter().any(|&(_, bound)| bound == Key::Alt(key))
+        || matches!(key, '<' | '>')
+        || (key == 'x' && config.allow_exec)
+}
+
+/// Split a --source-cmd command line into the program and its arguments
+fn parse_source_cmd(command: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let words = shell_words::split(command)?;
+    if words.is_empty() {
+        return Err("--source-cmd needs a command".into());
+    }
+
+    Ok(words)
+}
+
+/// Whether keys type into the query or act on the results, with --vim
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// j and k move, h goes up a directory and l into the selected one
+    Normal,
+    /// typing, the only mode without --vim
+    Insert,
+}
+
+/// When to draw in color
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ColorMode {
+    /// unless NO_COLOR is set or TERM is `dumb`
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn parse(when: &str) -> Result<ColorMode, Box<dyn Error>> {
+        match when {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("unknown --color: {}", when).into()),
+        }
+    }
+
+    /// Whether to color this run. Auto doesn't go by whether stdout is a
+    /// terminal because fdi draws on the tty, and stdout is piped
+    /// whenever the picked path is captured
+    pub fn enabled(self) -> bool {
+        let env = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+
+        match self {
+            ColorMode::Auto => {
+                env("NO_COLOR").is_none() && env("TERM").is_none_or(|term| term != "dumb")
+            }
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// Where the prompt and status lines sit relative to the results
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum HeaderPosition {
+    /// above the results, with the best match right below the status
+    #[default]
+    Top,
+    /// below the results, which then grow upwards from the status line
+    Bottom,
+}
+
+impl HeaderPosition {
+    pub fn parse(position: &str) -> Result<HeaderPosition, Box<dyn Error>> {
+        match position {
+            "top" => Ok(HeaderPosition::Top),
+            "bottom" => Ok(HeaderPosition::Bottom),
+            _ => Err(format!("unknown header position: {}", position).into()),
+        }
+    }
+
+    /// Where the header lines and the results go on a terminal `height`
+    /// rows high. The results get the rows the header leaves, less a spare
+    /// one at their far end
+    pub fn rows(self, height: u16) -> ScreenRows {
+        // the prompt and the status line
+        let header = 2;
+        let region_rows = height.saturating_sub(header + 1);
+
+        match self {
+            HeaderPosition::Top => ScreenRows {
+                prompt: 1,
+                status: 2,
+                region_top: header + 1,
+                region_rows,
+            },
+            HeaderPosition::Bottom => ScreenRows {
+                prompt: height,
+                status: height.saturating_sub(1),
+                region_top: 2,
+                region_rows,
+            },
+        }
+    }
+}
+
+/// Where the preview pane goes, taking half of the results' room
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum PreviewPosition {
+    /// to the right of the results
+    #[default]
+    Right,
+    /// above the results, with a rule under it, for narrow terminals
+    Top,
+}
+
+impl PreviewPosition {
+    pub fn parse(position: &str) -> Result<PreviewPosition, Box<dyn Error>> {
+        match position {
+            "right" => Ok(PreviewPosition::Right),
+            "top" => Ok(PreviewPosition::Top),
+            _ => Err(format!("unknown preview position: {}", position).into()),
+        }
+    }
+}
+
+/// The rows of the screen, counting from 1, that each part of the UI is
+/// drawn on
+#[derive(Debug, Clone, Copy)]
+struct ScreenRows {
+    prompt: u16,
+    status: u16,
+    /// the first row of the results' region, border included
+    region_top: u16,
+    /// how many rows the results' region takes
+    region_rows: u16,
+}
+
+/// A known-good arrangement of the layout options set in one go with
+/// `--layout`. It only sets the options it covers, so later flags can
+/// still adjust a preset
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LayoutPreset {
+    /// prompt on top, no border or padding
+    Default,
+    /// prompt at the bottom with the results growing up from it, like fzf
+    Reverse,
+    /// prompt on top with the results framed by a rounded border and a
+    /// column of padding
+    Framed,
+    /// prompt on top and the preview shown to the right of the results
+    PreviewRight,
+    /// prompt on top and the preview shown above the results
+    PreviewTop,
+}
+
+impl LayoutPreset {
+    pub fn parse(preset: &str) -> Result<LayoutPreset, Box<dyn Error>> {
+        match preset {
+            "default" => Ok(LayoutPreset::Default),
+            "reverse" => Ok(LayoutPreset::Reverse),
+            "framed" => Ok(LayoutPreset::Framed),
+            "preview-right" => Ok(LayoutPreset::PreviewRight),
+            "preview-top" => Ok(LayoutPreset::PreviewTop),
+            _ => Err(format!("unknown layout: {}", preset).into()),
+        }
+    }
+
+    pub fn apply(self, config: &mut Config) {
+        let (header, border, padding) = match self {
+            LayoutPreset::Default => (HeaderPosition::Top, BorderStyle::None, 0),
+            LayoutPreset::Reverse => (HeaderPosition::Bottom, BorderStyle::None, 0),
+            LayoutPreset::Framed => (HeaderPosition::Top, BorderStyle::Rounded, 1),
+            LayoutPreset::PreviewRight | LayoutPreset::PreviewTop => {
+                (HeaderPosition::Top, BorderStyle::None, 0)
+            }
+        };
+        let preview = match self {
+            LayoutPreset::PreviewRight => Some(PreviewPosition::Right),
+            LayoutPreset::PreviewTop => Some(PreviewPosition::Top),
+            _ => None,
+        };
+
+        config.header = header;
+        config.border = border;
+        config.padding = padding;
+        if let Some(position) = preview {
+            config.preview = true;
+            config.preview_position = position;
+        }
+    }
+}
+
+/// How the border around the results is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum BorderStyle {
+    #[default]
+    None,
+    Rounded,
+    Sharp,
+    Double,
+}
+
+impl BorderStyle {
+    pub fn parse(style: &str) -> Result<BorderStyle, Box<dyn Error>> {
+        match style {
+            "none" => Ok(BorderStyle::None),
+            "rounded" => Ok(BorderStyle::Rounded),
+            "sharp" => Ok(BorderStyle::Sharp),
+            "double" => Ok(BorderStyle::Double),
+            _ => Err(format!("unknown border style: {}", style).into()),
+        }
+    }
+
+    /// The top left, top right, bottom left and bottom right corners
+    /// followed by the horizontal and vertical edges
+    pub fn chars(self) -> Option<[char; 6]> {
+        match self {
+            BorderStyle::None => None,
+            BorderStyle::Rounded => Some(['╭', '╮', '╰', '╯', '─', '│']),
+            BorderStyle::Sharp => Some(['┌', '┐', '└', '┘', '─', '│']),
+            BorderStyle::Double => Some(['╔', '╗', '╚', '╝', '═', '║']),
+        }
+    }
+}
+
+/// Whether a path is shown as it is under the current directory or in
+/// full
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PathStyle {
+    /// under the directory results are listed in for display, and under
+    /// the one fdi was run from when printed, like fd's own output
+    Relative,
+    Absolute,
+}
+
+impl PathStyle {
+    pub fn parse(style: &str) -> Result<PathStyle, Box<dyn Error>> {
+        match style {
+            "relative" => Ok(PathStyle::Relative),
+            "absolute" => Ok(PathStyle::Absolute),
+            _ => Err(format!("unknown path style: {}", style).into()),
+        }
+    }
+}
+
+/// The kind of entries fd lists, cycled through with Alt-T
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum EntryType {
+    #[default]
+    All,
+    Files,
+    Dirs,
+    Symlinks,
+}
+
+impl EntryType {
+    pub fn parse(types: &str) -> Result<EntryType, Box<dyn Error>> {
+        match types {
+            "all" => Ok(EntryType::All),
+            "files" => Ok(EntryType::Files),
+            "dirs" => Ok(EntryType::Dirs),
+            "symlinks" => Ok(EntryType::Symlinks),
+            _ => Err(format!("unknown kind of entry: {}", types).into()),
+        }
+    }
+
+    /// The kind after this one, going round them all
+    pub fn next(self) -> EntryType {
+        match self {
+            EntryType::All => EntryType::Files,
+            EntryType::Files => EntryType::Dirs,
+            EntryType::Dirs => EntryType::Symlinks,
+            EntryType::Symlinks => EntryType::All,
+        }
+    }
+
+    /// What fd's `--type` takes for this kind, nothing when it's all
+    pub fn fd_type(self) -> Option<&'static str> {
+        match self {
+            EntryType::All => None,
+            EntryType::Files => Some("f"),
+            EntryType::Dirs => Some("d"),
+            EntryType::Symlinks => Some("l"),
+        }
+    }
+}
+
+/// How the case of the query is matched
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum CaseMode {
+    /// ignore case until the query has an uppercase letter in it, like fzf
+    #[default]
+    Smart,
+    /// always ignore case
+    Ignore,
+    /// always match case
+    Respect,
+}
+
+impl CaseMode {
+    pub fn parse(mode: &str) -> Result<CaseMode, Box<dyn Error>> {
+        match mode {
+            "smart" => Ok(CaseMode::Smart),
+            "ignore" => Ok(CaseMode::Ignore),
+            "respect" => Ok(CaseMode::Respect),
+            _ => Err(format!("unknown case mode: {}", mode).into()),
+        }
+    }
+
+    /// A matcher that treats case this way, which for smart case is
+    /// decided again for each query as it's matched
+    pub fn matcher(self) -> SkimMatcherV2 {
+        match self {
+            CaseMode::Smart => SkimMatcherV2::default().smart_case(),
+            CaseMode::Ignore => SkimMatcherV2::default().ignore_case(),
+            CaseMode::Respect => SkimMatcherV2::default().respect_case(),
+        }
+    }
+
+    /// The same for substring matching
+    pub fn substring(self) -> Substring {
+        let ignore_case = match self {
+            CaseMode::Smart => None,
+            CaseMode::Ignore => Some(true),
+            CaseMode::Respect => Some(false),
+        };
+        Substring { ignore_case }
+    }
+
+    /// The same for nucleo's matching
+    #[cfg(feature = "nucleo")]
+    pub fn nucleo(self) -> Nucleo {
+        use nucleo_matcher::pattern::CaseMatching;
+
+        let case = match self {
+            CaseMode::Smart => CaseMatching::Smart,
+            CaseMode::Ignore => CaseMatching::Ignore,
+            CaseMode::Respect => CaseMatching::Respect,
+        };
+        Nucleo { case }
+    }
+}
+
+/// Which fuzzy matching algorithm scores the results
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Algo {
+    #[default]
+    Skim,
+    #[cfg(feature = "nucleo")]
+    Nucleo,
+    /// the query as a plain substring, switched to and from with Ctrl-E
+    Exact,
+}
+
+impl Algo {
+    pub fn parse(algo: &str) -> Result<Algo, Box<dyn Error>> {
+        match algo {
+            "skim" => Ok(Algo::Skim),
+            "exact" => Ok(Algo::Exact),
+            #[cfg(feature = "nucleo")]
+            "nucleo" => Ok(Algo::Nucleo),
+            #[cfg(not(feature = "nucleo"))]
+            "nucleo" => Err("nucleo matching needs fdi built with --features nucleo".into()),
+            _ => Err(format!("unknown matching algorithm: {}", algo).into()),
+        }
+    }
+
+    /// The algorithm, treating case the way `case` says
+    pub fn scorer(self, case: CaseMode) -> Box<dyn Scorer> {
+        match self {
+            Algo::Skim => Box::new(case.matcher()),
+            #[cfg(feature = "nucleo")]
+            Algo::Nucleo => Box::new(case.nucleo()),
+            Algo::Exact => Box::new(case.substring()),
+        }
+    }
+}
+
+/// The whitespace trimmed from incoming lines. Some sources pad their
+/// lines, which throws off truncation and highlighting, while for others
+/// the whitespace is part of the entry, so only line endings go by default
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Trim {
+    /// keep lines byte for byte
+    None,
+    /// strip trailing `\r`, left over from `\r\n` line endings
+    #[default]
+    LineEnd,
+    /// strip all trailing whitespace
+    Trailing,
+    /// strip leading and trailing whitespace
+    Both,
+}
+
+impl Trim {
+    pub fn parse(trim: &str) -> Result<Trim, Box<dyn Error>> {
+        match trim {
+            "none" => Ok(Trim::None),
+            "line-end" => Ok(Trim::LineEnd),
+            "trailing" => Ok(Trim::Trailing),
+            "both" => Ok(Trim::Both),
+            _ => Err(format!("unknown trim mode: {}", trim).into()),
+        }
+    }
+
+    pub fn apply(self, line: String) -> String {
+        let trimmed = match self {
+            Trim::None => &line[..],
+            Trim::LineEnd => line.trim_end_matches(['\r', '\n']),
+            Trim::Trailing => line.trim_end(),
+            Trim::Both => line.trim(),
+        };
+
+        if trimmed.len() == line.len() {
+            line
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
+
+/// A fallback for descending when the query isn't a directory. They're tried
+/// in the configured order until one handles it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BadDir {
+    /// descend into the selected result instead, if it's a matching directory
+    Result,
+    /// say so on the status line
+    Message,
+    /// draw the query in red until the next key press
+    Flash,
+}
+
+impl BadDir {
+    pub fn parse(step: &str) -> Result<BadDir, Box<dyn Error>> {
+        match step {
+            "result" => Ok(BadDir::Result),
+            "message" => Ok(BadDir::Message),
+            "flash" => Ok(BadDir::Flash),
+            _ => Err(format!("unknown --on-bad-dir fallback: {}", step).into()),
+        }
+    }
+}
+
+/// What comes in for the main loop to act on, and what it does in
+/// response to a key press
+#[derive(Debug)]
+enum AppEvent {
+    /// the query changed to this pattern
+    Input(String),
+    /// move to a new directory and restart fd there
+    Dir(PathBuf),
+    /// restart fd in the current directory
+    Restart,
+    /// the terminal is now this many columns wide and rows high
+    Resize(u16, u16),
+    /// something failed that the user should hear about
+    Error(String),
+    /// move the selection by this many results, towards the worse ones
+    /// for positive steps
+    Move(i32),
+    /// list the bookmarked directories in place of the current one
+    Bookmarks,
+    /// this key was pressed
+    Key(Key),
+    /// this line came in on --control
+    Control(String),
+    /// the listing with this generation has these lines ready
+    Lines(u64, Vec<String>),
+    /// print the selection, or what's marked, and exit
+    Accept,
+    /// exit without printing anything more
+    Quit,
+    /// the sort thread is done matching the results
+    Sorted(Sorted),
+}
+
+/// A matching of the results against the query for the sort thread to do
+struct SortJob {
+    /// the generation of the matching, sent back with what it comes to
+    generation: u64,
+    output: Vec<OutputLine>,
+    unmatched: Vec<OutputLine>,
+    matcher: Arc<Matcher>,
+    pattern: String,
+    sort_mode: SortMode,
+    /// the results kept whether they match or not, and the directory
+    /// they're listed in
+    pinned: Vec<PathBuf>,
+    dir: PathBuf,
+}
+
+/// The results as the sort thread matched and sorted them
+#[derive(Debug)]
+struct Sorted {
+    generation: u64,
+    output: Vec<OutputLine>,
+    unmatched: Vec<OutputLine>,
+}
+
+/// Start the thread that does the jobs sent to it, sending each one's
+/// results back to `events`. Matching a big listing takes a while, and
+/// this way keys and drawing don't have to wait for it. A job `latest`
+/// has moved on from is given up on, even partway through
+fn spawn_sorter(
+    events: mpsc::UnboundedSender<AppEvent>,
+    latest: Generation,
+) -> std::sync::mpsc::Sender<SortJob> {
+    let (jobs, next) = std::sync::mpsc::channel::<SortJob>();
+
+    std::thread::spawn(move || {
+        for mut job in next {
+            let generation = job.generation;
+            let (pinned, dir) = (&job.pinned, &job.dir);
+            let finished = latest.is_current(generation)
+                && update_fuzz_unless(
+                    &mut job.output,
+                    &mut job.unmatched,
+                    &job.matcher,
+                    &job.pattern,
+                    job.sort_mode,
+                    |line| pinned.contains(&dir.join(&line.data)),
+                    || !latest.is_current(generation),
+                );
+            if !finished {
+                continue;
+            }
+
+            let sorted = Sorted {
+                generation: job.generation,
+                output: job.output,
+                unmatched: job.unmatched,
+            };
+            if events.send(AppEvent::Sorted(sorted)).is_err() {
+                return;
+            }
+        }
+    });
+
+    jobs
+}
+
+/// How long each message stays on the status line, unless a key press
+/// dismisses it sooner
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+/// The messages for the status line, shown one at a time in the order
+/// they came, each for `MESSAGE_TIME`
+#[derive(Debug, Default)]
+struct Messages {
+    queue: VecDeque<String>,
+    /// when the one showing is due to go
+    until: Option<Instant>,
+}
+
+impl Messages {
+    /// Put `message` on the status line once those before it have gone
+    pub fn notify(&mut self, message: String) {
+        self.queue.push_back(message);
+        if self.until.is_none() {
+            self.until = Some(Instant::now() + MESSAGE_TIME);
+        }
+    }
+
+    /// The message showing, if there is one
+    pub fn current(&self) -> Option<&str> {
+        self.queue.front().map(String::as_str)
+    }
+
+    /// Drop the message showing once its time is up, bringing on the next.
+    /// Whether one went
+    pub fn expire(&mut self) -> bool {
+        if self.until.is_none_or(|until| Instant::now() < until) {
+            return false;
+        }
+
+        self.queue.pop_front();
+        self.until = Some(Instant::now() + MESSAGE_TIME).filter(|_| !self.queue.is_empty());
+        true
+    }
+
+    /// Drop every message, whether one was showing
+    pub fn clear(&mut self) -> bool {
+        self.until = None;
+        let showing = !self.queue.is_empty();
+        self.queue.clear();
+        showing
+    }
+}
+
+/// A rename of one of the results being typed in
+struct Rename {
+    /// the full path of the entry being renamed
+    from: PathBuf,
+    /// the new file name typed so far
+    name: String,
+    /// set once Enter found `name` already taken, until it's answered
+    confirm: bool,
+}
+
+/// The actions config.toml's `[keys]` can bind more keys to, with the key
+/// each one has by default
+const ACTIONS: [(&str, Key); 51] = [
+    ("quit", Key::Ctrl('c')),
+    ("accept", Key::Char('\n')),
+    ("print", Key::Alt('\n')),
+    ("mark", Key::Char('\t')),
+    ("collect", Key::Alt('c')),
+    ("descend", Key::Right),
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("page-up", Key::PageUp),
+    ("page-down", Key::PageDown),
+    ("first", Key::CtrlHome),
+    ("last", Key::CtrlEnd),
+    ("clear-query", Key::Ctrl('u')),
+    ("delete-word", Key::Ctrl('w')),
+    ("back", Key::AltLeft),
+    ("forward", Key::AltRight),
+    ("previous-dir", Key::Alt('-')),
+    ("zoom", Key::Alt('z')),
+    ("deeper", Key::Alt('.')),
+    ("shallower", Key::Alt(',')),
+    ("match-names", Key::Alt('b')),
+    ("toggle-dirs", Key::Alt('D')),
+    ("cycle-type", Key::Alt('T')),
+    ("toggle-hidden", Key::Alt('H')),
+    ("toggle-follow", Key::Alt('F')),
+    ("toggle-ignore", Key::Alt('I')),
+    ("exclude", Key::Alt('e')),
+    ("fd-args", Key::Alt('a')),
+    ("redraw", Key::Ctrl('l')),
+    ("refresh", Key::Ctrl('r')),
+    ("refine", Key::Alt('i')),
+    ("copy-path", Key::Ctrl('y')),
+    ("copy-name", Key::Alt('n')),
+    ("edit", Key::Ctrl('o')),
+    ("open", Key::Alt('o')),
+    ("exact", Key::Ctrl('e')),
+    ("cycle-sort", Key::Alt('S')),
+    ("sort-by-name", Key::Alt('s')),
+    ("bookmark", Key::Ctrl('b')),
+    ("bookmarks", Key::Alt('B')),
+    ("grep", Key::Alt('g')),
+    ("wrap", Key::Alt('w')),
+    ("preview", Key::Ctrl('p')),
+    ("pager", Key::Alt('v')),
+    ("preview-down", Key::Ctrl('j')),
+    ("preview-up", Key::Ctrl('k')),
+    ("lock-preview", Key::Alt('P')),
+    ("metadata", Key::Alt('l')),
+    ("show-output", Key::Alt('q')),
+    ("pin", Key::Alt('p')),
+    ("unpin-all", Key::Alt('u')),
+];
+
+/// What --help prints
+const USAGE: &str = "\
+usage: fdi [OPTIONS] [DIR] [-- FD_ARGS...]
+
+Pick a path from what fd lists in DIR, the current directory by default,
+and print it.
+
+Listing:
+      --from-file FILE     read the paths from FILE instead of fd, - for stdin
+      --start-dir DIR      start in DIR
+      --type KIND          list all, files, dirs or symlinks
+      --dirs-only          list only directories
+      --depth LEVELS       list this many levels deep
+      --follow             follow symlinks into directories
+      --dedup              drop lines listed already
+      --keep-root          keep an entry naming the listed directory
+      --trim MODE          trim none, line-end, trailing or both
+      --max-results N      keep only the best N results
+      --fd-bin PROGRAM     the fd to run, also $FDI_FD_BIN
+      --source-cmd CMD     list with CMD instead of fd, {} for the directory,
+                           also $FDI_SOURCE_CMD
+      --fd-args ARGS       arguments fd always gets
+      --fd-retries N       run fd again this many times when it fails
+      --dir-settle MS      wait this long for directory changes to settle
+      --on-bad-dir STEPS   what Enter tries when the query isn't a
+                           directory, in order: result to go into the
+                           top result, message to say so, flash to draw
+                           the query red; result,message by default
+      --logical            keep symlinks in the path like cd -L
+      --physical           resolve symlinks in the path, the default
+
+Matching:
+      --query QUERY        start out with QUERY typed
+      --algo NAME          match with skim, exact or nucleo
+      --case MODE          smart, ignore or respect case
+      --min-score N        the lowest score that still matches
+      --show-below         count the lines that scored under it
+      --transform PART     match full, basename, dirname or strip-extension
+      --fold-accents       match accented chars by their base letter
+      --sort MODE          order by auto, score, depth or name
+      --select-1           print the best match for --query without the UI
+      --exit-0             with --select-1, fail when more than one matches
+      --count              print matched/total for --query without the UI
+
+Display:
+      --layout PRESET      default, reverse, framed: the header and
+                           border; preview-right, preview-top: the
+                           preview shown beside or above the results
+      --header POSITION    the prompt at the top or bottom
+      --preview            start with the preview shown
+      --preview-position WHERE  the preview to the right or top
+      --border STYLE       none, rounded, sharp or double
+      --padding COLUMNS    space between the results and the border
+      --color WHEN         auto, always or never, also NO_COLOR
+      --case-shade COLOR   color matches that ignored case
+      --max-color-runs N   the most color switches in a line
+      --dim-prefix         dim the directories the results share
+      --full-home          don't abbreviate the home directory to ~
+      --dir-slash          draw directories with a trailing separator
+      --display-paths STYLE  draw results relative or absolute
+      --ellipsis WHERE     cut long results at the end or middle
+      --metadata           show sizes and ages
+      --marked-size        show the total size of the marked files
+      --allow-wrap         let Alt-w wrap long results
+      --min-width COLUMNS  only draw the query below this width
+      --loading-grace MS   wait this long before saying it's loading
+
+Keys:
+      --vim                start in vim's normal mode: j/k move, g/G go
+                           to the first/last result, / starts typing
+      --cycle              wrap the selection round at either end
+      --no-mouse           leave the mouse to the terminal
+      --jump KEY=PATH      make Alt-KEY go to PATH
+      --rename-key KEY     rename with Alt-KEY
+      --allow-exec         let Alt-x run an executable result
+      --exec-args ARGS     run it with ARGS, {} standing for its path
+      --early-enter        descend before anything is listed
+      --keep-query         keep the query in a new directory
+      --zoom-keep-query    keep the query when zooming
+
+Output:
+      --print-paths STYLE  print relative or absolute paths
+      --print0             end printed paths with NUL
+      --shell-quote        single quote printed paths, for eval; a
+                           path a line, so not with --print0
+      --output FILE        print the paths to FILE instead of stdout
+      --cd                 print `cd -- 'DIR'` for a picked directory
+      --cd-fd N            write that to file descriptor N instead
+      --trace FILE         write the directories visited, - for stderr
+      --mirror PATH        write the top results here as they change
+      --mirror-count N     how many results go to the mirror
+      --log-file FILE      write diagnostics to FILE
+      --control PATH       take commands from a named pipe or file:
+                           cd DIR, query TEXT
+      --control-fd N       take them from file descriptor N instead
+  -h, --help               print this and exit
+
+Printing as you go:
+  Alt-Enter prints the selection, or everything marked with Tab, and
+  keeps fdi open. The paths go out flushed at once, styled like Enter's
+  by --print-paths, --print0 and --shell-quote, to stdout or --output;
+  --output is created by the first of them and keeps what comes after.
+  A directory is printed as a path even with --cd, which only goes to
+  the one Enter picks. Printing clears the marks, Alt-c's collection
+  still waits for the exit, and once anything is printed quitting
+  exits 0.
+
+Shell:
+  --cd is for a shell function to run what it prints, in bash or zsh:
+    fcd() { local cmd; cmd=$(fdi --cd --cd-fd 3 \"$@\" 3>&1 1>&2) && eval \"$cmd\"; }
+
+Exit status:
+  0  a result was picked and printed
+  1  fdi was quit without picking anything, or --select-1 found no answer
+  2  the command line or running fd went wrong
+";
+
+/// The prompt before the query, with the current directory in it
+const DEFAULT_PROMPT: &str = " > {dir} ";
+
+/// How many columns the size and age of a result take, with the space
+/// before them
+const METADATA_WIDTH: u16 = 16;
+
+/// The size and age of the entry at `path` for the metadata column,
+/// blank when it can't be stat'ed
+fn metadata_column(path: &Path) -> String {
+    let meta = match path.metadata() {
+        Ok(meta) => meta,
+        Err(_) => return " ".repeat(METADATA_WIDTH as usize),
+    };
+
+    let size = if meta.is_dir() {
+        "-".to_string()
+    } else {
+        human_size(meta.len())
+    };
+    let age = meta
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map_or_else(|| "-".to_string(), human_age);
+
+    format!(" {:>6} {:>8}", size, age)
+}
+
+/// The fewest rows the preview pane takes above the results, or else
+/// it isn't shown
+const MIN_PREVIEW_ROWS: u16 = 3;
+
+/// How much of a file the preview pane reads looking for its first lines
+const PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// The most lines taken in from the listing, and the longest spent waiting
+/// on more of them, before the results are sorted and drawn again
+const BATCH_LINES: usize = 200;
+const BATCH_TIME: Duration = Duration::from_millis(30);
+
+/// The least time between two draws, so a burst of keys and listed lines
+/// is drawn once rather than once for each
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// How soon a second click on a result has to come to accept it
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Parse a color given by name, like `yellow` or `light-blue`, or by its
+/// 256-color index
+fn parse_color(name: &str) -> Result<u8, Box<dyn Error>> {
+    let names = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    if let Ok(value) = name.parse() {
+        return Ok(value);
+    }
+
+    let (light, base) = match name.strip_prefix("light-") {
+        Some(base) => (8, base),
+        None => (0, name),
+    };
+
+    names
+        .iter()
+        .position(|&known| known == base)
+        .map(|value| value as u8 + light)
+        .ok_or_else(|| format!("unknown color: {}", name).into())
+}
+
+/// Parse a key to go with Alt, which has to be a single character
+fn parse_key(key: &str) -> Result<char, Box<dyn Error>> {
+    let mut chars = key.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(key), None) => Ok(key),
+        _ => Err(format!("key should be a single character: {}", key).into()),
+    }
+}
+
+/// Parse a --jump mapping of a single key to a directory, `KEY=PATH`,
+/// with a leading `~` standing for the home directory. An empty path
+/// unmaps the key
+fn parse_jump(jump: &str) -> Result<(char, Option<PathBuf>), Box<dyn Error>> {
+    let (key, path) = jump
+        .split_once('=')
+        .ok_or_else(|| format!("--jump needs KEY=PATH, got {}", jump))?;
+
+    let key = parse_key(key).map_err(|err| format!("--jump {}", err))?;
+
+    if path.is_empty() {
+        return Ok((key, None));
+    }
+
+    let path = match (path.strip_prefix('~'), home_dir()) {
+        (Some(rest), Some(home))
+            if rest.is_empty() || rest.starts_with(std::path::is_separator) =>
+        {
+            PathBuf::from(format!("{}{}", home, rest))
+        }
+        _ => PathBuf::from(path),
+    };
+
+    Ok((key, Some(path)))
+}
+
+/// Lines of candidate paths, read from fd or from a file list
+type Candidates = Lines<BufReader<Box<dyn AsyncRead + Unpin + Send>>>;
+
+/// A listing of candidates that is being read
+struct Source {
+    lines: Candidates,
+    /// resolves to fd's exit status once it finishes, `None` for a file list
+    status: Option<oneshot::Receiver<ExitStatus>>,
+    /// when the listing was started
+    started: Instant,
+    /// stops fd when sent to or dropped, `None` without a process
+    kill: Option<oneshot::Sender<()>>,
+    /// set by a kill, after which nothing more is read
+    cancel: Cancel,
+}
+
+impl Source {
+    /// The next line of the listing, with none past a kill, not even the
+    /// ones fd had already written
+    pub async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        let line = self.lines.next_line().await?;
+        Ok(self.cancel.admit(line))
+    }
+
+    /// Read the listing on a task of its own, which sends its lines on to
+    /// `events` in batches, so the results are sorted once for each.
+    /// They're tagged with `generation`, telling them apart from those of
+    /// an earlier listing still on their way
+    pub fn listen(self, generation: u64, events: mpsc::UnboundedSender<AppEvent>) -> Listing {
+        let Source {
+            mut lines,
+            status,
+            started,
+            kill,
+            cancel,
+        } = self;
+        let reading = cancel.clone();
+
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                // take in what else the listing has ready as one batch
+                let mut batch = vec![line];
+                let until = Instant::now() + BATCH_TIME;
+                while batch.len() < BATCH_LINES {
+                    let left = until.saturating_duration_since(Instant::now());
+                    match tokio::time::timeout(left, lines.next_line()).await {
+                        Ok(Ok(Some(line))) => batch.push(line),
+                        _ => break,
+                    }
+                }
+
+                // a kill while the batch was coming together drops it
+                // along with anything after it
+                if reading.is_cancelled()
+                    || events.send(AppEvent::Lines(generation, batch)).is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        Listing {
+            generation,
+            status,
+            started,
+            kill,
+            cancel,
+        }
+    }
+}
+
+/// A listing whose lines are being read and sent on by `Source::listen`
+struct Listing {
+    /// counts up with each listing, starting from 0 for the first
+    generation: u64,
+    /// resolves to fd's exit status once it finishes, `None` for a file list
+    status: Option<oneshot::Receiver<ExitStatus>>,
+    /// when the listing was started
+    started: Instant,
+    /// stops fd when sent to or dropped, `None` without a process
+    kill: Option<oneshot::Sender<()>>,
+    /// set by a kill, after which no more lines are sent
+    cancel: Cancel,
+}
+
+impl Listing {
+    /// Stop the process producing the listing, if there is one, so it
+    /// doesn't keep walking a tree nobody is looking at, and stop sending
+    /// on what it's listed
+    pub fn kill(&mut self) {
+        self.cancel.cancel();
+        if let Some(kill) = self.kill.take() {
+            let _ = kill.send(());
+        }
+    }
+}
+
+/// The switches fd is run with that can be flipped while fdi is open
+#[derive(Clone, Copy)]
+struct FdFlags {
+    /// list hidden files, toggled with Alt-H
+    hidden: bool,
+    /// the kind of entries listed, cycled with Alt-T and with Alt-D
+    /// switching between directories and everything
+    types: EntryType,
+    /// follow symlinks, toggled with Alt-F
+    follow: bool,
+}
+
+impl FdFlags {
+    /// The switches as fdi starts out with them
+    pub fn new(config: &Config) -> FdFlags {
+        FdFlags {
+            hidden: true,
+            types: config.types,
+            follow: config.follow,
+        }
+    }
+}
+
+/// List `dir` with --source-cmd when there is one, and otherwise with fd
+async fn spawn_source(
+    config: &Config,
+    dir: &Path,
+    depth: Option<u32>,
+    flags: FdFlags,
+    excludes: &[String],
+    extra_args: &[String],
+) -> Result<Source, Box<dyn Error>> {
+    match &config.source_cmd {
+        Some(command) => spawn_command(command, dir),
+        None => spawn_fd(config, dir, depth, flags, excludes, extra_args).await,
+    }
+}
+
+/// Run a --source-cmd in `dir`, with `{}` in its arguments swapped for
+/// the directory's path. fd's switches don't reach it
+fn spawn_command(command: &[String], dir: &Path) -> Result<Source, Box<dyn Error>> {
+    let path = dir.to_string_lossy();
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(command[1..].iter().map(|arg| arg.replace("{}", &path)));
+    cmd.current_dir(dir);
+
+    let source = spawn_listing(cmd).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => format!("could not find {}", command[0]),
+        _ => format!("could not run {}: {}", command[0], err),
+    })?;
+
+    Ok(source)
+}
+
+async fn spawn_fd(
+    config: &Config,
+    dir: &Path,
+    depth: Option<u32>,
+    flags: FdFlags,
+    excludes: &[String],
+    extra_args: &[String],
+) -> Result<Source, Box<dyn Error>> {
+    let mut cmd = Command::new(&config.fd_bin);
+
+    if flags.hidden {
+        cmd.arg("-H");
+    }
+    if let Some(fd_type) = flags.types.fd_type() {
+        cmd.arg("--type").arg(fd_type);
+    }
+    if flags.follow {
+        cmd.arg("--follow");
+    }
+    if let Some(depth) = depth {
+        cmd.arg("--max-depth").arg(depth.to_string());
+    }
+    for pattern in excludes {
+        cmd.arg("--exclude").arg(pattern);
+    }
+    cmd.args(&config.fd_args);
+    cmd.args(extra_args);
+    cmd.current_dir(dir);
+
+    let source = spawn_listing(cmd).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => format!(
+            "could not find {}, install fd from https://github.com/sharkdp/fd \
+             (the fd-find package on Debian and Ubuntu, run with --fd-bin fdfind)",
+            config.fd_bin
+        ),
+        _ => format!("could not run {}: {}", config.fd_bin, err),
+    })?;
+
+    Ok(source)
+}
+
+/// Search the contents of the files under `dir` for `pattern` with rg,
+/// which lists a `path:line:text` line for each hit
+async fn spawn_rg(dir: &Path, pattern: &str, hidden: bool) -> Result<Source, Box<dyn Error>> {
+    let mut cmd = Command::new("rg");
+
+    // the query is taken as it's typed, with the same smart case as
+    // the fuzzy matching
+    cmd.args(["--line-number", "--no-heading", "--color", "never"]);
+    cmd.args(["--fixed-strings", "--smart-case", "--max-columns", "500"]);
+    if hidden {
+        cmd.arg("--hidden");
+    }
+    cmd.arg("--").arg(pattern);
+    cmd.current_dir(dir);
+    // unreadable files aren't worth drawing over the UI for
+    cmd.stderr(Stdio::null());
+
+    let source = spawn_listing(cmd).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            "could not find rg, install ripgrep from https://github.com/BurntSushi/ripgrep"
+                .to_string()
+        }
+        _ => format!("could not run rg: {}", err),
+    })?;
+
+    Ok(source)
+}
+
+/// The path a result stands for, which for an rg hit is the file it's in
+fn result_path(line: &str, grep: bool) -> &str {
+    match grep_hit(line).filter(|_| grep) {
+        Some((path, _)) => path,
+        None => line,
+    }
+}
+
+/// Run `cmd` and read its output as the listing
+fn spawn_listing(mut cmd: Command) -> std::io::Result<Source> {
+    // pipe the child's stdout to the programs stdout
+    cmd.stdout(Stdio::piped());
+    // and don't outlive fdi if it exits mid-walk
+    cmd.kill_on_drop(true);
+
+    log::debug!("running {:?}", cmd.as_std());
+    let mut child = cmd.spawn()?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .expect("child did not have a handle to stdout");
+
+    let stdout: Box<dyn AsyncRead + Unpin + Send> = Box::new(stdout);
+    let reader = BufReader::new(stdout).lines();
+    let (status_tx, status_rx) = oneshot::channel();
+    let (kill_tx, kill_rx) = oneshot::channel::<()>();
+
+    tokio::spawn(async move {
+        // a kill, or the Source going away, stops fd early
+        let finished = tokio::select! {
+            status = child.wait() => Some(status),
+            _ = kill_rx => None,
+        };
+        let status = match finished {
+            Some(status) => status,
+            None => child.kill().await.and(child.wait().await),
+        }
+        .expect("child process encountered an error");
+
+        log::debug!("child status was: {}", status);
+        // nobody listening just means the listing was replaced
+        let _ = status_tx.send(status);
+    });
+
+    Ok(Source {
+        lines: reader,
+        status: Some(status_rx),
+        started: Instant::now(),
+        kill: Some(kill_tx),
+        cancel: Cancel::default(),
+    })
+}
+
+async fn read_list(list: &Path) -> Result<Source, Box<dyn Error>> {
+    let file: Box<dyn AsyncRead + Unpin + Send> = Box::new(File::open(list).await?);
+
+    Ok(Source {
+        lines: BufReader::new(file).lines(),
+        status: None,
+        started: Instant::now(),
+        kill: None,
+        cancel: Cancel::default(),
+    })
+}
+
+/// The lines piped into fdi, read once in the background and kept so
+/// every directory can be listed from them like from a --from-file list
+#[derive(Debug, Clone, Default)]
+struct Piped {
+    lines: Arc<Mutex<Vec<String>>>,
+    /// set once stdin has run out
+    done: Arc<AtomicBool>,
+}
+
+impl Piped {
+    /// Start reading stdin into the list
+    pub fn read_stdin() -> Piped {
+        let piped = Piped::default();
+        let reading = piped.clone();
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                reading.lines.lock().unwrap().push(line);
+            }
+            reading.done.store(true, Ordering::SeqCst);
+        });
+
+        piped
+    }
+
+    /// A list of `lines` that's already complete
+    pub fn from_lines(lines: Vec<String>) -> Piped {
+        Piped {
+            lines: Arc::new(Mutex::new(lines)),
+            done: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// A source with every line piped in so far, then the rest as they come
+    pub fn source(&self) -> Source {
+        let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+        let piped = self.clone();
+        let cancel = Cancel::default();
+        let copying = cancel.clone();
+
+        tokio::spawn(async move {
+            let mut next = 0;
+            // a kill stops the copying as well as the source going away
+            while !copying.is_cancelled() {
+                // checked before taking the lines, so none that come in
+                // between are missed
+                let done = piped.done.load(Ordering::SeqCst);
+                let batch = piped.lines.lock().unwrap()[next..].to_vec();
+                next += batch.len();
+
+                for line in batch {
+                    let line = line + "\n";
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+
+                if done {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        let reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(reader);
+        Source {
+            lines: BufReader::new(reader).lines(),
+            status: None,
+            started: Instant::now(),
+            kill: None,
+            cancel,
+        }
+    }
+}
+
+/// A source with nothing to list, standing in while the directory settles
+fn idle_source() -> Source {
+    let empty: Box<dyn AsyncRead + Unpin + Send> = Box::new(tokio::io::empty());
+
+    Source {
+        lines: BufReader::new(empty).lines(),
+        status: None,
+        started: Instant::now(),
+        kill: None,
+        cancel: Cancel::default(),
+    }
+}
+
+/// Start listing the candidates for `dir`, from the --from-file list if
+/// one was given and from fd otherwise, or the hits for `grep` in the
+/// files there when searching their contents
+async fn open_source(
+    config: &Config,
+    dir: &Path,
+    depth: Option<u32>,
+    flags: FdFlags,
+    excludes: &[String],
+    extra_args: &[String],
+    grep: Option<&str>,
+) -> Result<Source, Box<dyn Error>> {
+    #[cfg(feature = "archives")]
+    if let Some((archive, inner)) = archive_split(dir) {
+        return read_archive(archive, inner).await;
+    }
+
+    // every line of every file matches nothing typed yet
+    match grep {
+        Some("") => return Ok(idle_source()),
+        Some(pattern) => return spawn_rg(dir, pattern, flags.hidden).await,
+        None => {}
+    }
+
+    if let Some(piped) = &config.piped {
+        return Ok(piped.source());
+    }
+
+    match &config.from_file {
+        Some(list) => read_list(list).await,
+        None => spawn_source(config, dir, depth, flags, excludes, extra_args).await,
+    }
+}
+
+/// Split a path that goes into a .zip or .tar file into the archive and
+/// the path inside it, `None` for a path that doesn't go into one
+#[cfg(feature = "archives")]
+fn archive_split(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    path.ancestors()
+        .find(|ancestor| {
+            let archive = matches!(
+                ancestor.extension().and_then(|ext| ext.to_str()),
+                Some("zip") | Some("tar")
+            );
+            archive && ancestor.is_file()
+        })
+        .map(|archive| {
+            let inner = path.strip_prefix(archive).unwrap_or(Path::new(""));
+            (archive.to_path_buf(), inner.to_path_buf())
+        })
+}
+
+/// The names of everything stored in a .zip or .tar file
+#[cfg(feature = "archives")]
+fn archive_entries(archive: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let file = std::fs::File::open(archive)?;
+
+    if archive.extension().is_some_and(|ext| ext == "zip") {
+        let zip = zip::ZipArchive::new(file)?;
+        return Ok(zip.file_names().map(str::to_string).collect());
+    }
+
+    let mut names = Vec::new();
+    for entry in tar::Archive::new(file).entries()? {
+        let entry = entry?;
+        let mut name = entry.path()?.to_string_lossy().into_owned();
+        if entry.header().entry_type().is_dir() && !name.ends_with('/') {
+            name.push('/');
+        }
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+/// Whether `inner` is a directory inside `archive`, which the archive's
+/// root always is
+#[cfg(feature = "archives")]
+fn archive_has_dir(archive: &Path, inner: &Path) -> bool {
+    if inner.as_os_str().is_empty() {
+        return true;
+    }
+
+    let depth = inner.components().count();
+    archive_entries(archive).is_ok_and(|names| {
+        names
+            .iter()
+            .map(Path::new)
+            .any(|name| name.starts_with(inner) && name.components().count() > depth)
+    })
+}
+
+/// List what's under `inner` in `archive` the way fd lists a directory,
+/// every file and directory below it relative to it, with a trailing
+/// slash on the directories. Directories the archive only implies by
+/// the paths of what's in them are listed too
+#[cfg(feature = "archives")]
+async fn read_archive(archive: PathBuf, inner: PathBuf) -> Result<Source, Box<dyn Error>> {
+    let listing = tokio::task::spawn_blocking(move || {
+        let mut listing = std::collections::BTreeSet::new();
+
+        // the error is made a String to get it back out of the blocking task
+        for name in archive_entries(&archive).map_err(|err| err.to_string())? {
+            let components: Vec<_> = Path::new(&name).components().collect();
+
+            for len in 1..=components.len() {
+                let entry: PathBuf = components[..len].iter().collect();
+                let is_dir = len < components.len() || name.ends_with('/');
+
+                if let Ok(rel) = entry.strip_prefix(&inner) {
+                    if !rel.as_os_str().is_empty() {
+                        let mut rel = rel.to_string_lossy().into_owned();
+                        if is_dir {
+                            rel.push('/');
+                        }
+                        listing.insert(rel);
+                    }
+                }
+            }
+        }
+
+        let mut bytes = Vec::new();
+        for entry in listing {
+            bytes.extend_from_slice(entry.as_bytes());
+            bytes.push(b'\n');
+        }
+        Ok::<_, String>(bytes)
+    })
+    .await??;
+
+    let listing: Box<dyn AsyncRead + Unpin + Send> = Box::new(std::io::Cursor::new(listing));
+
+    Ok(Source {
+        lines: BufReader::new(listing).lines(),
+        status: None,
+        started: Instant::now(),
+        kill: None,
+        cancel: Cancel::default(),
+    })
+}
+
+/// Turn `path` into the directory to navigate to, or `None` if it isn't one
+fn resolve_dir(config: &Config, path: &Path) -> Option<PathBuf> {
+    // an archive and the directories inside it are gone into like any
+    // other directory, with the archive resolved in place of the path
+    #[cfg(feature = "archives")]
+    if let Some((archive, inner)) = archive_split(&normalize_lexically(path)) {
+        let archive = if config.logical {
+            archive
+        } else {
+            canonicalize(&archive).ok()?
+        };
+
+        let dir = archive.join(&inner).components().collect();
+        return Some(dir).filter(|_| archive_has_dir(&archive, &inner));
+    }
+
+    // a directory that's there but can't be resolved, like one under a
+    // component we may not read, is still gone into by its plain path
+    if config.logical {
+        Some(normalize_lexically(path)).filter(|path| path.is_dir())
+    } else {
+        canonicalize(path)
+            .ok()
+            .or_else(|| Some(normalize_lexically(path)))
+            .filter(|path| path.is_dir())
+    }
+}
+
+/// Why `path` couldn't be gone into, for the status line
+fn why_not_dir(path: &Path) -> &'static str {
+    bad_dir_reason(
+        std::fs::metadata(path).map(|meta| meta.is_dir()),
+        path.symlink_metadata().is_ok(),
+    )
+}
+
+/// Whether `dir` is there but may not be read, so fd would list nothing
+fn permission_denied(dir: &Path) -> bool {
+    is_permission_denied(&std::fs::read_dir(dir))
+}
+
+/// fd options that would stop it listing just the current directory a
+/// path per line, by searching elsewhere, running commands on the results
+/// or printing something other than their paths
+const CONFLICTING_FD_ARGS: [&str; 11] = [
+    "--base-directory",
+    "--search-path",
+    "--exec",
+    "-x",
+    "--exec-batch",
+    "-X",
+    "--print0",
+    "-0",
+    "--list-details",
+    "-l",
+    "--format",
+];
+
+/// Check that `args` can be passed on to fd without breaking the listing
+fn check_fd_args(args: &[String]) -> Result<(), String> {
+    for arg in args {
+        let name = arg.split('=').next().unwrap_or(arg);
+
+        if CONFLICTING_FD_ARGS.contains(&name) {
+            return Err(format!("fd's {} can't be used with fdi", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// The directory fdi starts in. In logical mode this is `$PWD` when it
+/// points at the current directory, so a shell that cd'd through a
+/// symlink hands that path over untouched
+fn start_dir(config: &Config) -> Result<PathBuf, Box<dyn Error>> {
+    let physical = canonicalize(Path::new("."))?;
+
+    if config.logical {
+        if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from) {
+            if pwd.is_absolute() && canonicalize(&pwd).ok().as_ref() == Some(&physical) {
+                return Ok(pwd);
+            }
+        }
+    }
+
+    Ok(physical)
+}
+
+/// Whether the locale says the terminal can draw UTF-8, checking the
+/// same variables as setlocale in the same order
+fn unicode_terminal() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|locale| !locale.is_empty()))
+        .map(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+        .unwrap_or(false)
+}
+
+/// Draw a box from row `top` to row `bottom` spanning the full `width`
+fn draw_border(
+    stdout: &mut impl Write,
+    border: [char; 6],
+    top: u16,
+    bottom: u16,
+    width: u16,
+) -> Result<(), Box<dyn Error>> {
+    let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = border;
+    let edge = horizontal
+        .to_string()
+        .repeat(width.saturating_sub(2) as usize);
+
+    write!(stdout, "{}{}{}{}", goto(1, top), top_left, edge, top_right)?;
+
+    for row in top + 1..bottom {
+        write!(
+            stdout,
+            "{}{}{}{}",
+            goto(1, row),
+            vertical,
+            goto(width, row),
+            vertical
+        )?;
+    }
+
+    write!(
+        stdout,
+        "{}{}{}{}",
+        goto(1, bottom),
+        bottom_left,
+        edge,
+        bottom_right
+    )?;
+
+    Ok(())
+}
+
+/// What the preview pane shows for `path`: `rows` lines of a file, or
+/// entries of a directory, from the `skip`th on and cut to `width` columns
+fn preview_lines(path: &Path, skip: usize, rows: usize, width: usize) -> Vec<String> {
+    let fit = |line: &str| -> String {
+        line.replace('\t', "    ")
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(width)
+            .collect()
+    };
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) => return vec![fit(&err.to_string())],
+    };
+
+    if metadata.is_dir() {
+        let mut names: Vec<String> = match std::fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| {
+                    let mut name = entry.file_name().to_string_lossy().into_owned();
+                    if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                        name.push('/');
+                    }
+                    name
+                })
+                .collect(),
+            Err(err) => return vec![fit(&err.to_string())],
+        };
+        names.sort();
+
+        return names
+            .iter()
+            .skip(skip)
+            .take(rows)
+            .map(|name| fit(name))
+            .collect();
+    }
+
+    // fifos and devices could block or never end, so only files are read
+    if !metadata.is_file() {
+        return Vec::new();
+    }
+
+    let mut head = Vec::new();
+    let read =
+        std::fs::File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut head));
+    if let Err(err) = read {
+        return vec![fit(&err.to_string())];
+    }
+
+    if head.contains(&0) {
+        return vec![fit("binary file")];
+    }
+
+    String::from_utf8_lossy(&head)
+        .lines()
+        .skip(skip)
+        .take(rows)
+        .map(fit)
+        .collect()
+}
+
+/// The command that opens a path with the default application for it
+#[cfg(target_os = "macos")]
+fn opener() -> std::process::Command {
+    std::process::Command::new("open")
+}
+
+/// The command that opens a path with the default application for it
+#[cfg(windows)]
+fn opener() -> std::process::Command {
+    let mut cmd = std::process::Command::new("cmd");
+    // the empty argument is the window title `start` would otherwise
+    // take the path for
+    cmd.args(["/C", "start", ""]);
+    cmd
+}
+
+/// The command that opens a path with the default application for it
+#[cfg(all(unix, not(target_os = "macos")))]
+fn opener() -> std::process::Command {
+    std::process::Command::new("xdg-open")
+}
+
+/// Whether stdin is a list being piped or redirected in, rather than the
+/// terminal or something like /dev/null that was just left attached
+#[cfg(unix)]
+fn stdin_piped() -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    std::fs::metadata("/dev/stdin")
+        .map(|metadata| metadata.file_type())
+        .is_ok_and(|kind| kind.is_fifo() || kind.is_file())
+}
+
+/// Whether stdin is a list being piped or redirected in
+#[cfg(not(unix))]
+fn stdin_piped() -> bool {
+    use std::io::IsTerminal;
+
+    !std::io::stdin().is_terminal()
+}
+
+/// Whether `path` is a file that can be run
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether `path` is a file that can be run
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Run `program` in `dir` on the terminal, out of raw mode, until it
+/// exits, passing it `args` with any `{}` in them swapped for its path
+fn run_program(
+    stdout: &mut impl Terminal,
+    program: &Path,
+    args: &[String],
+    dir: &Path,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    let path = program.to_string_lossy();
+    let mut cmd = std::process::Command::new(program);
+
+    cmd.args(args.iter().map(|arg| arg.replace("{}", &path)));
+    cmd.current_dir(dir);
+
+    stdout.run_in_foreground(cmd)
+}
+
+/// Open `path` in `$EDITOR`, or vi when that isn't set, and wait for it.
+/// Given a line, it's passed as `+LINE` first, which most editors take
+fn edit_file(
+    stdout: &mut impl Terminal,
+    path: &Path,
+    line: Option<u64>,
+    dir: &Path,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    let editor = std::env::var("EDITOR")
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // $EDITOR is often a command with flags, like `code -w`
+    let words = shell_words::split(&editor)?;
+    let (program, args) = words.split_first().ok_or("$EDITOR is empty")?;
+    let mut cmd = std::process::Command::new(program);
+
+    cmd.args(args);
+    if let Some(line) = line {
+        cmd.arg(format!("+{}", line));
+    }
+    cmd.arg(path);
+    cmd.current_dir(dir);
+
+    stdout
+        .run_in_foreground(cmd)
+        .map_err(|err| format!("{}: {}", program, err).into())
+}
+
+/// Read `path` in `$PAGER`, or less when that isn't set, and wait for it
+fn page_file(
+    stdout: &mut impl Terminal,
+    path: &Path,
+    dir: &Path,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    // $PAGER can have flags too, like `less -R`
+    let words = shell_words::split(&pager)?;
+    let (program, args) = words.split_first().ok_or("$PAGER is empty")?;
+    let mut cmd = std::process::Command::new(program);
+
+    cmd.args(args);
+    cmd.arg(path);
+    cmd.current_dir(dir);
+
+    stdout
+        .run_in_foreground(cmd)
+        .map_err(|err| format!("{}: {}", program, err).into())
+}
+
+/// Open `path` in the OS default application without waiting for it
+fn open_path(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut cmd = opener();
+
+    cmd.arg(path);
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::null());
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| format!("{}: {}", cmd.get_program().to_string_lossy(), err))?;
+
+    // reap the opener whenever it finishes so it doesn't linger
+    std::thread::spawn(move || child.wait());
+
+    Ok(())
+}
+
+/// The commands that can put text on the clipboard here, best first
+fn clipboard_commands() -> Vec<Vec<&'static str>> {
+    if cfg!(target_os = "macos") {
+        vec![vec!["pbcopy"]]
+    } else if cfg!(windows) {
+        vec![vec!["clip"]]
+    } else {
+        let mut commands = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(vec!["wl-copy"]);
+        }
+        commands.push(vec!["xclip", "-selection", "clipboard"]);
+        commands.push(vec!["xsel", "--clipboard", "--input"]);
+        commands
+    }
+}
+
+/// Put `text` on the system clipboard with the first clipboard command
+/// that's installed
+fn copy_to_clipboard(text: &str) -> Result<(), Box<dyn Error>> {
+    for command in clipboard_commands() {
+        let child = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+
+        let status = child.wait()?;
+        return if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{} failed ({})", command[0], status).into())
+        };
+    }
+
+    Err("no clipboard command found".into())
+}
+
+/// The directory fdi keeps its files in, `$XDG_CONFIG_HOME/fdi` or
+/// under `~/.config` when that isn't set
+fn config_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir().map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config_home.join("fdi"))
+}
+
+/// Where the bookmarks are kept
+fn bookmarks_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("bookmarks"))
+}
+
+/// The settings in config.toml, `None` when it's missing or isn't valid
+/// TOML so that everything keeps its default
+fn read_config_file() -> Option<toml::Table> {
+    let text = std::fs::read_to_string(config_dir()?.join("config.toml")).ok()?;
+
+    text.parse().ok()
+}
+
+/// The keys bound in config.toml's `[keys]` table, like `down = "ctrl-j"`
+/// or a list of them, each to the default key of its action, which goes
+/// on working too. A key bound to two actions is an error
+fn load_keymap(file: Option<&toml::Table>) -> Result<HashMap<Key, Key>, Box<dyn Error>> {
+    let mut keymap = HashMap::new();
+
+    let keys = match file.and_then(|file| file.get("keys")) {
+        Some(toml::Value::Table(keys)) => keys,
+        Some(_) => return Err("[keys] in config.toml has to be a table".into()),
+        None => return Ok(keymap),
+    };
+
+    let mut bound_to: HashMap<Key, &str> = HashMap::new();
+    for (action, names) in keys {
+        let (_, default) = ACTIONS
+            .iter()
+            .find(|(name, _)| name == action)
+            .ok_or_else(|| format!("unknown action in [keys]: {}", action))?;
+        let names = match names {
+            toml::Value::String(name) => Some(vec![name.as_str()]),
+            toml::Value::Array(names) => names.iter().map(|name| name.as_str()).collect(),
+            _ => None,
+        }
+        .ok_or_else(|| format!("[keys] {} has to be a key or a list of them", action))?;
+
+        for name in names {
+            let key = Key::parse(name)?;
+            if let Some(other) = bound_to.insert(key, action) {
+                return Err(format!("{} is bound to both {} and {}", name, other, action).into());
+            }
+            keymap.insert(key, *default);
+        }
+    }
+
+    Ok(keymap)
+}
+
+/// The prompt template from `$FDI_PROMPT`, or else config.toml's `prompt`
+fn load_prompt(file: Option<&toml::Table>) -> String {
+    std::env::var("FDI_PROMPT")
+        .ok()
+        .filter(|prompt| !prompt.is_empty())
+        .or_else(|| match file?.get("prompt")? {
+            toml::Value::String(prompt) => Some(prompt.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| DEFAULT_PROMPT.to_string())
+}
+
+/// The colors set in the `[colors]` table of config.toml, as names or
+/// 256-color indices. Anything missing or not a color keeps its default
+fn load_theme(file: Option<&toml::Table>) -> Theme {
+    let mut theme = Theme::default();
+
+    let colors = match file.and_then(|file| file.get("colors")) {
+        Some(toml::Value::Table(colors)) => colors,
+        _ => return theme,
+    };
+    let color = |key: &str| match colors.get(key)? {
+        toml::Value::String(name) => parse_color(name).ok(),
+        toml::Value::Integer(value) => (0..=255).contains(value).then_some(*value as u8),
+        _ => None,
+    };
+
+    theme.matched = color("match").unwrap_or(theme.matched);
+    theme.selected = color("selected").unwrap_or(theme.selected);
+    theme.prompt = color("prompt").or(theme.prompt);
+
+    theme
+}
+
+/// The bookmarked directories, one per line of the bookmarks file. A file
+/// that's missing or can't be read just means there are none yet
+fn read_bookmarks() -> Vec<String> {
+    bookmarks_file()
+        .and_then(|file| std::fs::read_to_string(file).ok())
+        .map(|bookmarks| {
+            bookmarks
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The patterns in the ignore file next to config.toml, one per line with
+/// blank lines and `#` comments skipped, for leaving lines that contain
+/// any of them out of every listing
+fn read_ignores() -> Vec<String> {
+    config_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join("ignore")).ok())
+        .map(|ignores| {
+            ignores
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether `line` has one of the ignore file's patterns in it
+fn is_ignored(ignores: &[String], line: &str) -> bool {
+    ignores
+        .iter()
+        .any(|pattern| line.contains(pattern.as_str()))
+}
+
+/// Add `dir` to the bookmarks file, `false` if it was already there
+fn add_bookmark(dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let file = bookmarks_file().ok_or("no config directory to keep bookmarks in")?;
+    let dir = dir.to_string_lossy();
+
+    if read_bookmarks().iter().any(|bookmark| *bookmark == dir) {
+        return Ok(false);
+    }
+
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut out = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file)?;
+    writeln!(out, "{}", dir)?;
+
+    Ok(true)
+}
+
+/// Write the directories visited, in order, to `target`, where `-` is
+/// stderr
+fn write_trace(target: &Path, trace: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let mut out: Box<dyn Write> = if target == Path::new("-") {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::fs::File::create(target)?)
+    };
+
+    for dir in trace {
+        writeln!(out, "{}", dir.display())?;
+    }
+
+    Ok(())
+}
+
+/// Write `paths` to the --mirror sink, one per line and then an empty
+/// line to end the batch. Nothing waits on a pipe nobody is reading
+fn write_mirror(target: &Path, paths: &[PathBuf]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).append(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // don't block opening or writing a fifo with no reader
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+
+    let mut sink = options.open(target)?;
+
+    let mut batch = String::new();
+    for path in paths {
+        batch.push_str(&path.to_string_lossy());
+        batch.push('\n');
+    }
+    batch.push('\n');
+
+    sink.write_all(batch.as_bytes())
+}
+
+/// Diagnostics for --log-file, appended to it a line at a time. Without
+/// one nothing is set up and the log macros do nothing
+struct FileLog {
+    file: Mutex<std::fs::File>,
+    /// the most detailed level written
+    level: log::LevelFilter,
+}
+
+impl FileLog {
+    /// Log everything from here on to the end of the file at `path`
+    fn init(path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+
+        let level = log::LevelFilter::Debug;
+        log::set_boxed_logger(Box::new(FileLog {
+            file: Mutex::new(file),
+            level,
+        }))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl log::Log for FileLog {
+    /// Only fdi's own records, not those of the crates it uses
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= self.level && (target == "fdi" || target.starts_with("fdi::"))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// The terminal the UI draws on, in raw mode until it's dropped, which
+/// puts it back with the cursor showing on a normal exit and on a panic
+/// unwinding out of `run` alike
+struct Screen {
+    tty: std::fs::File,
+    /// whether clicks and the scroll wheel come through as keys
+    mouse: bool,
+    /// what's been drawn since the last flush, written out in one go by it
+    frame: Vec<u8>,
+    /// the frame written out last, to skip writing the same one again
+    last_frame: Vec<u8>,
+    /// holds the key reader off while another program has the terminal
+    input: InputPause,
+}
+
+impl Screen {
+    pub fn open(mouse: bool, input: InputPause) -> Result<Screen, Box<dyn Error>> {
+        let tty = open_tty()?;
+        restore_on_panic(&tty);
+        crossterm::terminal::enable_raw_mode()?;
+        let mut screen = Screen {
+            tty,
+            mouse,
+            frame: Vec::new(),
+            last_frame: Vec::new(),
+            input,
+        };
+        // the prompt draws a cursor of its own where typing goes
+        write!(screen.tty, "{}", crossterm::cursor::Hide)?;
+        screen.capture_mouse(true)?;
+
+        Ok(screen)
+    }
+
+    /// Have clicks and the scroll wheel come through or not, when they're
+    /// taken at all
+    pub fn capture_mouse(&mut self, on: bool) -> std::io::Result<()> {
+        if self.mouse && on {
+            self.tty.execute(EnableMouseCapture)?;
+        } else if self.mouse {
+            self.tty.execute(DisableMouseCapture)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Terminal for Screen {
+    fn size(&self) -> std::io::Result<(u16, u16)> {
+        crossterm::terminal::size()
+    }
+
+    fn forget_frame(&mut self) {
+        self.last_frame.clear();
+    }
+
+    /// Out of raw mode and with our key reader held off
+    fn run_in_foreground(
+        &mut self,
+        mut cmd: std::process::Command,
+    ) -> Result<ExitStatus, Box<dyn Error>> {
+        write!(
+            self,
+            "{}{}{}",
+            Clear(ClearType::All),
+            goto(1, 1),
+            crossterm::cursor::Show
+        )?;
+        self.capture_mouse(false)?;
+        self.flush()?;
+        crossterm::terminal::disable_raw_mode()?;
+
+        let status = {
+            let _held = self.input.hold();
+            cmd.status()
+        };
+
+        // back into raw mode whether or not the program could be started
+        crossterm::terminal::enable_raw_mode()?;
+        self.capture_mouse(true)?;
+        self.forget_frame();
+        write!(self, "{}", crossterm::cursor::Hide)?;
+
+        Ok(status?)
+    }
+}
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.frame.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Write out the frame drawn since the last flush, unless it's the
+    /// same as the one already on the terminal
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.frame != self.last_frame {
+            self.tty.write_all(&self.frame)?;
+            self.tty.flush()?;
+        }
+        self.last_frame = std::mem::take(&mut self.frame);
+
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // leaving raw mode again is harmless, so this is fine after the
+        // panic hook already left it
+        let _ = self.tty.write_all(&self.frame);
+        let _ = write!(self.tty, "{}", crossterm::cursor::Show);
+        let _ = self.capture_mouse(false);
+        let _ = self.tty.flush();
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// The terminal itself, whatever stdin and stdout are redirected to
+#[cfg(not(windows))]
+fn open_tty() -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+}
+
+/// The console itself, whatever stdin and stdout are redirected to
+#[cfg(windows)]
+fn open_tty() -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("CONOUT$")
+}
+
+/// Have a panic on the main thread take the terminal out of raw mode
+/// before its message is printed, so that the message comes out readable.
+/// Panics on the runtime's threads leave the UI be
+fn restore_on_panic(tty: &std::fs::File) {
+    // a handle of its own, so it's still open whenever a panic comes
+    let tty = match tty.try_clone() {
+        Ok(tty) => tty,
+        Err(_) => return,
+    };
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().name() == Some("main") {
+            let _ = crossterm::terminal::disable_raw_mode();
+            let _ = write!(&tty, "{}", crossterm::cursor::Show);
+            let _ = (&tty).execute(DisableMouseCapture);
+        }
+        default_hook(info);
+    }));
+}
+
+/// `text` with the char at `at` drawn as the cursor, in reverse video,
+/// and a space for it after the end when that's where it is
+fn with_cursor(text: &str, at: usize) -> String {
+    let mut drawn = String::new();
+    let mut chars = text.chars();
+
+    drawn.extend(chars.by_ref().take(at));
+    drawn.push_str(&SetAttribute(Attribute::Reverse).to_string());
+    drawn.push(chars.next().unwrap_or(' '));
+    drawn.push_str(&SetAttribute(Attribute::NoReverse).to_string());
+    drawn.extend(chars);
+
+    drawn
+}
+
+/// Move the cursor to `column` and `row`, counting from 1 like the
+/// drawing code does
+fn goto(column: u16, row: u16) -> crossterm::cursor::MoveTo {
+    crossterm::cursor::MoveTo(column.saturating_sub(1), row.saturating_sub(1))
+}
+
+/// A key press, in the shape the key handling matches on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Char(char),
+    Alt(char),
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    AltLeft,
+    AltRight,
+    Home,
+    End,
+    CtrlHome,
+    CtrlEnd,
+    PageUp,
+    PageDown,
+    Backspace,
+    Delete,
+    Esc,
+    /// a left click at this column and row, counting from 1
+    Click(u16, u16),
+    ScrollUp,
+    ScrollDown,
+    /// a key that does nothing
+    Null,
+}
+
+impl Key {
+    /// The key pressed, `None` for releases and keys nothing is bound to.
+    /// Enter and Tab come through as the chars they type
+    pub fn from_event(event: KeyEvent) -> Option<Key> {
+        if event.kind == KeyEventKind::Release {
+            return None;
+        }
+
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = event.modifiers.contains(KeyModifiers::ALT);
+
+        let key = match event.code {
+            KeyCode::Char(ch) if ctrl => Key::Ctrl(ch.to_ascii_lowercase()),
+            KeyCode::Char(ch) if alt => Key::Alt(ch),
+            KeyCode::Char(ch) => Key::Char(ch),
+            KeyCode::Enter if alt => Key::Alt('\n'),
+            KeyCode::Enter => Key::Char('\n'),
+            KeyCode::Tab => Key::Char('\t'),
+            KeyCode::Left if alt => Key::AltLeft,
+            KeyCode::Right if alt => Key::AltRight,
+            KeyCode::Home if ctrl => Key::CtrlHome,
+            KeyCode::End if ctrl => Key::CtrlEnd,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::Left => Key::Left,
+            KeyCode::Right => Key::Right,
+            KeyCode::Home => Key::Home,
+            KeyCode::End => Key::End,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Delete => Key::Delete,
+            KeyCode::Esc => Key::Esc,
+            _ => return None,
+        };
+
+        Some(key)
+    }
+
+    /// The key written like `ctrl-j`, `alt-x`, `enter` or just `x`, as
+    /// config.toml's `[keys]` binds them
+    pub fn parse(name: &str) -> Result<Key, Box<dyn Error>> {
+        let single = |rest: &str| {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch),
+                _ => None,
+            }
+        };
+
+        let key = match name {
+            "enter" => Key::Char('\n'),
+            "tab" => Key::Char('\t'),
+            "space" => Key::Char(' '),
+            "esc" => Key::Esc,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "backspace" => Key::Backspace,
+            "delete" => Key::Delete,
+            "alt-enter" => Key::Alt('\n'),
+            "alt-left" => Key::AltLeft,
+            "alt-right" => Key::AltRight,
+            "ctrl-home" => Key::CtrlHome,
+            "ctrl-end" => Key::CtrlEnd,
+            _ => {
+                let ch = |rest| single(rest).ok_or_else(|| format!("unknown key: {}", name));
+                if let Some(rest) = name.strip_prefix("ctrl-") {
+                    Key::Ctrl(ch(rest)?.to_ascii_lowercase())
+                } else if let Some(rest) = name.strip_prefix("alt-") {
+                    Key::Alt(ch(rest)?)
+                } else {
+                    Key::Char(ch(name)?)
+                }
+            }
+        };
+
+        Ok(key)
+    }
+
+    /// The mouse button or wheel turn, `None` for moves and the buttons
+    /// nothing is bound to
+    pub fn from_mouse(event: MouseEvent) -> Option<Key> {
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                Some(Key::Click(event.column + 1, event.row + 1))
+            }
+            MouseEventKind::ScrollUp => Some(Key::ScrollUp),
+            MouseEventKind::ScrollDown => Some(Key::ScrollDown),
+            _ => None,
+        }
+    }
+}
+
+/// A command read from --control
+#[derive(Debug, PartialEq)]
+enum Control {
+    /// go to this directory, relative to the current one
+    Cd(String),
+    /// make this the query
+    Query(String),
+}
+
+impl Control {
+    /// Parse one line of --control, a command and what it takes
+    pub fn parse(line: &str) -> Result<Control, String> {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+
+        match command {
+            "cd" if !arg.is_empty() => Ok(Control::Cd(arg.to_string())),
+            "cd" => Err("cd needs a directory".to_string()),
+            "query" => Ok(Control::Query(arg.to_string())),
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+}
+
+/// Read the lines of --control on a thread of their own. A named pipe is
+/// opened again each time a writer is done with it, so any number of
+/// them can send commands one after another
+fn read_control(path: PathBuf) -> std::sync::mpsc::Receiver<String> {
+    let (send, lines) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || loop {
+        // opening a named pipe waits for a writer
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                log::debug!("could not open {}: {}", path.display(), err);
+                return;
+            }
+        };
+        for line in std::io::BufRead::lines(std::io::BufReader::new(file)) {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let line = line.trim();
+            if !line.is_empty() && send.send(line.to_string()).is_err() {
+                return;
+            }
+        }
+
+        // a descriptor fdi was started with is the one pipe, which has
+        // no writer left to wait for once it's been read to the end
+        if !is_fifo(&path) || path.starts_with("/dev/fd") {
+            return;
+        }
+    });
+
+    lines
+}
+
+/// Whether `path` is a named pipe
+fn is_fifo(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        path.metadata().is_ok_and(|meta| meta.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// The keys pressed on the terminal, read on a thread of their own that
+/// can be held off while another program has the terminal so it doesn't
+/// eat that program's keys
+struct TtyInput {
+    keys: std::sync::mpsc::Receiver<std::io::Result<Key>>,
+}
+
+/// A handle that stops the thread behind a `TtyInput` from reading
+#[derive(Clone)]
+struct InputPause {
+    paused: Arc<AtomicBool>,
+    reading: Arc<Mutex<()>>,
+}
+
+/// The reader thread is held off until this is dropped
+struct HeldInput<'a> {
+    _reading: std::sync::MutexGuard<'a, ()>,
+    paused: &'a AtomicBool,
+}
+
+impl TtyInput {
+    /// Start reading keys, setting `resized` whenever the window changes
+    /// size
+    pub fn open(resized: Arc<AtomicBool>) -> (TtyInput, InputPause) {
+        let (send, keys) = std::sync::mpsc::channel();
+        let pause = InputPause {
+            paused: Arc::new(AtomicBool::new(false)),
+            reading: Arc::new(Mutex::new(())),
+        };
+
+        let held = pause.clone();
+        std::thread::spawn(move || loop {
+            if held.paused.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            let _reading = held.reading.lock().unwrap_or_else(|err| err.into_inner());
+            let event = match crossterm::event::poll(Duration::from_millis(10)) {
+                Ok(false) => continue,
+                Ok(true) => crossterm::event::read(),
+                Err(err) => Err(err),
+            };
+
+            let key = match event {
+                Ok(Event::Key(event)) => match Key::from_event(event) {
+                    Some(key) => Ok(key),
+                    None => continue,
+                },
+                Ok(Event::Mouse(event)) => match Key::from_mouse(event) {
+                    Some(key) => Ok(key),
+                    None => continue,
+                },
+                Ok(Event::Resize(..)) => {
+                    resized.store(true, Ordering::Relaxed);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(err) => Err(err),
+            };
+            let failed = key.is_err();
+            if send.send(key).is_err() || failed {
+                return;
+            }
+        });
+
+        (TtyInput { keys }, pause)
+    }
+
+    /// The next key pressed, if one has been
+    pub fn try_key(&self) -> Option<std::io::Result<Key>> {
+        self.keys.try_recv().ok()
+    }
+}
+
+impl InputPause {
+    /// Wait for the reader thread to finish with any read it's in the
+    /// middle of, then keep it from starting another
+    pub fn hold(&self) -> HeldInput<'_> {
+        self.paused.store(true, Ordering::SeqCst);
+
+        HeldInput {
+            _reading: self.reading.lock().unwrap_or_else(|err| err.into_inner()),
+            paused: &self.paused,
+        }
+    }
+}
+
+impl Drop for HeldInput<'_> {
+    fn drop(&mut self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+fn clear_screen(stdout: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    write!(stdout, "{}{}", Clear(ClearType::All), goto(1, 1))?;
+
+    Ok(())
+}
+
+/// What the main loop does after an event
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// keep going
+    Continue,
+    /// leave the UI, printing what was accepted if anything was
+    Exit,
+}
+
+/// What the UI draws on: the terminal itself, or somewhere standing in
+/// for it
+trait Terminal: Write {
+    /// The columns and rows there are to draw in
+    fn size(&self) -> std::io::Result<(u16, u16)>;
+
+    /// Write the next frame out even if it's the same as the last one,
+    /// for when something else has drawn over the terminal since
+    fn forget_frame(&mut self);
+
+    /// Give the terminal to `cmd` until it exits
+    fn run_in_foreground(
+        &mut self,
+        cmd: std::process::Command,
+    ) -> Result<ExitStatus, Box<dyn Error>>;
+}
+
+/// Everything the UI keeps track of from one event to the next, drawing
+/// on `stdout`
+struct App<'a, W: Terminal> {
+    config: &'a Config,
+    /// where fdi was run from, which --from-file lists are relative to
+    base_dir: &'a Path,
+    /// the directory being listed
+    dir: PathBuf,
+    /// the keys bound in config.toml, to the actions' own keys
+    keymap: HashMap<Key, Key>,
+    stdout: W,
+    /// how many keys have been read but not yet handled, so matching a
+    /// big listing gives up on a query that's about to change
+    theme: Theme,
+    /// the entries are colored by kind like ls does when this is set
+    color: bool,
+    ls_colors: Option<LsColors>,
+    prompt_template: String,
+    /// the directory we were in before the last change, for Alt-- to go
+    /// back to
+    prev_dir: Option<PathBuf>,
+    /// the results Enter picked, printed once the terminal is restored
+    accepted: Vec<PathBuf>,
+    /// the results marked with Tab in this directory, for Enter to print
+    /// all of them
+    marked: HashSet<PathBuf>,
+    /// the sizes of the marked results for --marked-size, each stat'ed
+    /// the first time it's marked
+    marked_sizes: HashMap<PathBuf, u64>,
+    /// the results collected with Alt-c from any directory, printed in
+    /// the order they were collected when fdi exits, however it's quit
+    collected: Vec<PathBuf>,
+    /// stdout or --output, which Alt-Enter prints to as it goes along,
+    /// and how many paths it printed there
+    sink: Sink<'a>,
+    sent: usize,
+    /// every directory visited, for --trace
+    trace: Vec<PathBuf>,
+    /// the directories visited, for Alt-Left and Alt-Right
+    history: History,
+    /// the result selected in each directory when we left it, and the one
+    /// to select again as the listing comes in on getting back there
+    left_selected: HashMap<PathBuf, String>,
+    reselect: Option<String>,
+    /// listing the bookmarks from Alt-B rather than the directory
+    in_bookmarks: bool,
+    /// searching the contents of the files for the query with rg, from
+    /// Alt-g, rather than listing their names
+    grep: bool,
+    /// whether keys type or move around, with --vim starting out moving
+    vim: bool,
+    mode: Mode,
+    cycle: bool,
+    /// extra arguments for fd entered at runtime with Alt-a
+    extra_args: Vec<String>,
+    /// the arguments to go back to if fd rejects the ones just entered
+    prev_extra_args: Option<Vec<String>>,
+    /// patterns fd leaves out of the listing, added one at a time with
+    /// Alt-e, and what's been typed into that prompt while it's open
+    excludes: Vec<String>,
+    exclude_prompt: Option<String>,
+    /// how deep fd lists, changed with Alt-. and Alt-,
+    depth: Option<u32>,
+    /// what part of each line the query is matched against, switched
+    /// between --transform and just the file name with Alt-b
+    transform: Transform,
+    /// what fd lists: hidden files, only directories, through symlinks
+    fd_flags: FdFlags,
+    /// what has been typed into the fd arguments prompt while it's open
+    args_prompt: Option<String>,
+    /// the rename being typed in, opened with Alt and --rename-key
+    rename: Option<Rename>,
+    /// the program Alt-x is asking whether to run
+    exec_confirm: Option<PathBuf>,
+    /// how many times fd has been retried for the current listing, and
+    /// when to try again if a retry is due
+    fd_retries: u32,
+    retry_at: Option<Instant>,
+    /// when to list the current directory, after a run of quick
+    /// directory changes has settled
+    settle_at: Option<Instant>,
+    /// the listing being read
+    source: Listing,
+    /// where the listing sends its lines, and where the main loop takes
+    /// them from
+    events: mpsc::UnboundedSender<AppEvent>,
+    incoming: mpsc::UnboundedReceiver<AppEvent>,
+    /// the lines of the listing the query matches, best first
+    output: Vec<OutputLine>,
+    /// the lines of the listing the query doesn't match, kept aside to
+    /// match again when it changes
+    unmatched: Vec<OutputLine>,
+    /// patterns from the ignore file that hide the lines containing them,
+    /// whether they're doing so, switched with Alt-I, and the lines of the
+    /// current listing they've hidden, to bring back when switched off
+    ignores: Vec<String>,
+    ignoring: bool,
+    ignored: Vec<String>,
+    /// the lines of the current listing seen so far, for --dedup
+    seen: HashSet<String>,
+    /// whether --max-results has dropped lines of the current listing,
+    /// which is then listed again for a new query so they get another
+    /// chance
+    dropped: bool,
+    term_width: u16,
+    term_height: u16,
+    /// the query typed
+    input: String,
+    /// where in the query typing goes, in chars, moved with the arrow keys
+    cursor: usize,
+    /// results kept at the top whatever the query, by full path so they
+    /// come back when their directory is listed again
+    pinned: Vec<PathBuf>,
+    /// the result the actions work on, moved with the arrow keys, and
+    /// the first result shown, scrolled to keep the selection in view
+    selected: usize,
+    scroll: usize,
+    /// the rows the shown results were last drawn on, for clicks to land
+    /// on, and how many columns from the left the list takes
+    result_rows: Vec<(u16, usize)>,
+    list_columns: u16,
+    /// the result clicked last and when, as a second click accepts it
+    last_click: Option<(usize, Instant)>,
+    /// wrap long results instead of cutting them off, toggled by Alt-w
+    wrap: bool,
+    /// show the selected result in a pane right of the results, toggled
+    /// by Ctrl-p, and what it showed last so it's only read again when
+    /// the selection or the pane changes
+    preview: bool,
+    preview_cache: Option<(PathBuf, usize, usize, usize, Vec<String>)>,
+    /// show the size and age of each shown result at the right of its row
+    metadata: bool,
+    /// show on the status line exactly what Enter would print
+    show_output: bool,
+    /// how far down the preview is scrolled with Ctrl-j and Ctrl-k, and
+    /// the result it's scrolled for. A new selection starts at its rg
+    /// hit, or the top, unless Alt-P has locked the scroll where it is
+    preview_scroll: usize,
+    preview_for: Option<PathBuf>,
+    preview_locked: bool,
+    /// when the query will have settled long enough to mirror the results
+    mirror_at: Option<Instant>,
+    /// when to match the results against the query, once a burst of
+    /// typing has settled, so it's matched once for the last of it
+    match_at: Option<Instant>,
+    /// set by Alt-s to order the results by name until the query changes
+    name_sort: Option<NameSort>,
+    sort_mode: SortMode,
+    /// shown on the status line in turn, until their time is up or the
+    /// next key press
+    messages: Messages,
+    /// draw the query in red until the next key press
+    flash_input: bool,
+    /// the algorithm matching the query, which Ctrl-E switches to exact
+    /// matching and back
+    algo: Algo,
+    matcher: Arc<Matcher>,
+    /// where the results are sent to be matched against a new query
+    sorter: std::sync::mpsc::Sender<SortJob>,
+    /// counts up with each matching sent to the sort thread, and with
+    /// each change that leaves the one underway out of date
+    matching: Generation,
+    /// what's been listed since the results were sent to be matched, to
+    /// go in with them once they're back, `None` with no matching underway
+    arrived: Option<Vec<OutputLine>>,
+    home: Option<String>,
+    /// a border is only drawn where the box drawing chars will show up
+    border: Option<[char; 6]>,
+    /// what lists the directories, for saying what failed
+    lister: &'a str,
+    /// the spinner frame last drawn, to redraw when it moves on
+    last_spinner: Option<usize>,
+    /// what the results region said in place of any results last time,
+    /// to clear it once there's something else to draw there
+    last_placeholder: Option<&'static str>,
+    /// only draw again when something changed, so sitting idle doesn't
+    /// keep the terminal busy, and then at most once a frame
+    redraw: bool,
+    drawn_at: Option<Instant>,
+}
+
+impl<'a, W: Terminal> App<'a, W> {
+    /// Start out listing `dir`, with `stdout` cleared to draw on
+    pub async fn new(
+        config: &'a Config,
+        base_dir: &'a Path,
+        dir: PathBuf,
+        config_file: Option<&toml::Table>,
+        keymap: HashMap<Key, Key>,
+        mut stdout: W,
+    ) -> Result<App<'a, W>, Box<dyn Error>> {
+        let color = config.color.enabled();
+        let vim = config.vim
+            || matches!(
+                config_file.and_then(|file| file.get("vim")),
+                Some(toml::Value::Boolean(true))
+            );
+        let cycle = config.cycle
+            || matches!(
+                config_file.and_then(|file| file.get("cycle")),
+                Some(toml::Value::Boolean(true))
+            );
+        let ignores = read_ignores();
+        // get the term height so we don't display more
+        // output than we need
+        let (term_width, term_height) = stdout.size()?;
+        log::debug!("terminal is {}x{}", term_width, term_height);
+        let input = config.query.clone().unwrap_or_default();
+        let fd_flags = FdFlags::new(config);
+        let (events, incoming) = mpsc::unbounded_channel();
+        let source = open_source(config, &dir, config.depth, fd_flags, &[], &[], None)
+            .await?
+            .listen(0, events.clone());
+        let matching = Generation::default();
+        let sorter = spawn_sorter(events.clone(), matching.clone());
+
+        clear_screen(&mut stdout)?;
+
+        Ok(App {
+            config,
+            base_dir,
+            history: History::new(dir.clone()),
+            trace: vec![dir.clone()],
+            dir,
+            keymap,
+            stdout,
+            theme: load_theme(config_file),
+            color,
+            ls_colors: LsColors::from_env().filter(|_| color),
+            prompt_template: load_prompt(config_file),
+            prev_dir: None,
+            accepted: Vec::new(),
+            marked: HashSet::new(),
+            marked_sizes: HashMap::new(),
+            collected: Vec::new(),
+            sink: Sink::new(config, base_dir),
+            sent: 0,
+            left_selected: HashMap::new(),
+            reselect: None,
+            in_bookmarks: false,
+            grep: false,
+            vim,
+            mode: if vim { Mode::Normal } else { Mode::Insert },
+            cycle,
+            extra_args: Vec::new(),
+            prev_extra_args: None,
+            excludes: Vec::new(),
+            exclude_prompt: None,
+            depth: config.depth,
+            transform: config.transform,
+            fd_flags,
+            args_prompt: None,
+            rename: None,
+            exec_confirm: None,
+            fd_retries: 0,
+            retry_at: None,
+            settle_at: None,
+            source,
+            events,
+            incoming,
+            output: Vec::new(),
+            unmatched: Vec::new(),
+            ignoring: !ignores.is_empty(),
+            ignores,
+            ignored: Vec::new(),
+            seen: HashSet::new(),
+            dropped: false,
+            term_width,
+            term_height,
+            cursor: input.chars().count(),
+            input,
+            pinned: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            result_rows: Vec::new(),
+            list_columns: 0,
+            last_click: None,
+            wrap: false,
+            preview: config.preview,
+            preview_cache: None,
+            metadata: config.metadata,
+            show_output: false,
+            preview_scroll: 0,
+            preview_for: None,
+            preview_locked: false,
+            mirror_at: None,
+            match_at: None,
+            name_sort: None,
+            sort_mode: config.sort,
+            messages: Messages::default(),
+            flash_input: false,
+            algo: config.algo,
+            matcher: Arc::new(Matcher {
+                scorer: config.algo.scorer(config.case),
+                min_score: config.min_score,
+            }),
+            sorter,
+            matching,
+            arrived: None,
+            home: if config.full_home { None } else { home_dir() },
+            border: config.border.chars().filter(|_| unicode_terminal()),
+            lister: config
+                .source_cmd
+                .as_ref()
+                .map_or(config.fd_bin.as_str(), |command| command[0].as_str()),
+            last_spinner: None,
+            last_placeholder: None,
+            redraw: true,
+            drawn_at: None,
+        })
+    }
+
+    /// Read `source` in place of the listing there was, whose lines
+    /// stop coming in
+    fn listen(&mut self, source: Source) {
+        self.source.kill();
+        let generation = self.source.generation + 1;
+        self.source = source.listen(generation, self.events.clone());
+    }
+
+    /// Let go of the matching underway, with what it's matching about to
+    /// change under it
+    fn abandon_sort(&mut self) {
+        if self.arrived.take().is_some() {
+            self.matching.next();
+        }
+    }
+
+    /// Act on `event`, from a key, a --control command, the listing or
+    /// the main loop itself
+    pub async fn handle(&mut self, event: AppEvent) -> Result<Action, Box<dyn Error>> {
+        // the query before a key or a command clears it for a move that
+        // may turn out not to happen
+        let typed = self.input.clone();
+        self.redraw = true;
+
+        let event = match event {
+            AppEvent::Key(key) => self.key(key)?,
+            AppEvent::Control(line) => self.control(&line),
+            event => Some(event),
+        };
+        let event = match event {
+            Some(event) => event,
+            None => return Ok(Action::Continue),
+        };
+
+        // fd can't list a directory that can't be read, so rather than
+        // an empty listing stay where the results still are, matched
+        // against the query from before the move
+        if let AppEvent::Dir(new_dir) = &event {
+            let denied = permission_denied(new_dir);
+            if !settle_move(denied, new_dir, &typed, &mut self.input, &mut self.history) {
+                self.messages
+                    .notify(format!("permission denied: {}", new_dir.display()));
+                self.match_at = Some(Instant::now());
+
+                clear_screen(&mut self.stdout)?;
+                return Ok(Action::Continue);
+            }
+        }
+
+        match event {
+            AppEvent::Lines(generation, batch) if generation == self.source.generation => {
+                self.take_lines(batch)
+            }
+            // lines a listing sent before it was replaced, which would
+            // otherwise land among the new directory's
+            AppEvent::Lines(..) => {}
+            AppEvent::Input(pattern) => {
+                self.abandon_sort();
+                self.input = pattern;
+                self.name_sort = None;
+                self.reselect = None;
+                self.match_at = Some(Instant::now() + Duration::from_millis(50));
+                self.selected = 0;
+                self.mirror_at = Some(Instant::now() + Duration::from_millis(50));
+
+                clear_screen(&mut self.stdout)?;
+            }
+            AppEvent::Resize(width, height) => {
+                self.term_width = width;
+                self.term_height = height;
+
+                self.stdout.forget_frame();
+                clear_screen(&mut self.stdout)?;
+            }
+            AppEvent::Error(err) => {
+                self.messages.notify(err);
+
+                clear_screen(&mut self.stdout)?;
+            }
+            AppEvent::Move(by) => {
+                self.reselect = None;
+                self.selected = move_selection(self.selected, by, self.output.len(), self.cycle);
+
+                clear_screen(&mut self.stdout)?;
+            }
+            AppEvent::Bookmarks => {
+                self.abandon_sort();
+                self.source.kill();
+                self.output.clear();
+                self.unmatched.clear();
+                self.ignored.clear();
+                self.dropped = false;
+                self.seen.clear();
+                self.selected = 0;
+                self.reselect = None;
+                self.in_bookmarks = true;
+
+                self.listen(Piped::from_lines(read_bookmarks()).source());
+                self.settle_at = None;
+                self.retry_at = None;
+
+                clear_screen(&mut self.stdout)?;
+            }
+            AppEvent::Dir(_) | AppEvent::Restart => {
+                if let (AppEvent::Dir(_), Some(line)) = (&event, self.output.get(self.selected)) {
+                    if !self.in_bookmarks {
+                        self.left_selected
+                            .insert(self.dir.clone(), line.data.clone());
+                    }
+                }
+                self.in_bookmarks = false;
+                self.abandon_sort();
+                self.source.kill();
+                self.output.clear();
+                self.unmatched.clear();
+                self.ignored.clear();
+                self.dropped = false;
+                self.seen.clear();
+                self.selected = 0;
+
+                // a new directory is only listed once it's been
+                // settled on for a moment, then several changes in a
+                // row run fd once
+                let moved = if let AppEvent::Dir(new_dir) = event {
+                    self.prev_dir = Some(std::mem::replace(&mut self.dir, new_dir));
+                    self.name_sort = None;
+                    self.reselect = self.left_selected.get(&self.dir).cloned();
+                    self.marked.clear();
+                    self.marked_sizes.clear();
+                    if self.config.trace.is_some() {
+                        self.trace.push(self.dir.clone());
+                    }
+                    true
+                } else {
+                    false
+                };
+                if moved && !self.config.dir_settle.is_zero() {
+                    self.listen(idle_source());
+                    self.settle_at = Some(Instant::now() + self.config.dir_settle);
+                } else {
+                    let opened = open_source(
+                        self.config,
+                        &self.dir,
+                        self.depth,
+                        self.fd_flags,
+                        &self.excludes,
+                        &self.extra_args,
+                        self.grep.then_some(self.input.as_str()),
+                    )
+                    .await;
+                    // without rg go back to listing names rather than quit
+                    let source = match opened {
+                        Err(err) if self.grep => {
+                            self.grep = false;
+                            self.messages.notify(err.to_string());
+                            open_source(
+                                self.config,
+                                &self.dir,
+                                self.depth,
+                                self.fd_flags,
+                                &self.excludes,
+                                &self.extra_args,
+                                None,
+                            )
+                            .await?
+                        }
+                        opened => opened?,
+                    };
+                    self.listen(source);
+                    self.settle_at = None;
+                }
+                self.fd_retries = 0;
+                self.retry_at = None;
+
+                clear_screen(&mut self.stdout)?;
+            }
+            // print the selected result's full path and quit, for
+            // `cd "$(fdi)"`, or every result marked when some are
+            AppEvent::Accept => {
+                if !self.marked.is_empty() {
+                    self.accepted = self.marked.drain().collect();
+                    self.accepted.sort();
+                    return Ok(Action::Exit);
+                }
+                if let Some(line) = self.output.get(self.selected) {
+                    let path = result_path(&line.data, self.grep);
+                    self.accepted = vec![self.dir.join(path).components().collect()];
+                    return Ok(Action::Exit);
+                }
+            }
+            AppEvent::Quit => return Ok(Action::Exit),
+            AppEvent::Sorted(sorted) if self.matching.is_current(sorted.generation) => {
+                self.output = sorted.output;
+                self.unmatched = sorted.unmatched;
+
+                // what was listed meanwhile, already matched against the
+                // same query
+                let arrived = self.arrived.take().unwrap_or_default();
+                if !arrived.is_empty() {
+                    for line in arrived {
+                        self.keep_line(line);
+                    }
+                    sort_output(
+                        &mut self.output,
+                        self.name_sort,
+                        self.sort_mode,
+                        &self.config.query(&self.input),
+                    );
+                }
+                pin_to_top(&mut self.output, &self.dir, &self.pinned);
+                self.dropped |= cap_results(&mut self.output, self.config.max_results);
+            }
+            // matched against a query or a listing that's since changed
+            AppEvent::Sorted(_) => {}
+            // what a key or a command does is never another of them
+            AppEvent::Key(_) | AppEvent::Control(_) => {}
+        }
+
+        Ok(Action::Continue)
+    }
+
+    /// What `key` does, once what it does to the UI itself is done
+    fn key(&mut self, key: Key) -> Result<Option<AppEvent>, Box<dyn Error>> {
+        let exclude_chars = ['\n', '\t'];
+
+        self.flash_input = false;
+        if self.messages.clear() {
+            clear_screen(&mut self.stdout)?;
+        }
+
+        // the query can have been cleared or replaced under the cursor
+        self.cursor = self.cursor.min(self.input.chars().count());
+
+        // in normal mode letters stand in for the keys that already
+        // move and change directories, and nothing types, while Esc
+        // in insert mode goes back to normal instead of clearing
+        let prompting = self.args_prompt.is_some()
+            || self.exclude_prompt.is_some()
+            || self.exec_confirm.is_some()
+            || self.rename.is_some();
+
+        // a click selects the result it lands on and another one soon
+        // after accepts it, and the wheel moves like the arrows, while
+        // a prompt takes neither
+        let key = match key {
+            Key::Click(..) | Key::ScrollUp | Key::ScrollDown if prompting => Key::Null,
+            Key::Click(column, row) => match self
+                .result_rows
+                .iter()
+                .find(|&&(at, _)| at == row && column <= self.list_columns)
+            {
+                Some(&(_, index)) => {
+                    let again = self
+                        .last_click
+                        .is_some_and(|(last, at)| last == index && at.elapsed() < DOUBLE_CLICK);
+                    self.last_click = Some((index, Instant::now())).filter(|_| !again);
+                    self.reselect = None;
+                    self.selected = index;
+                    if again {
+                        Key::Char('\n')
+                    } else {
+                        Key::Null
+                    }
+                }
+                None => Key::Null,
+            },
+            Key::ScrollUp => Key::Up,
+            Key::ScrollDown => Key::Down,
+            _ => key,
+        };
+        // keys bound in config.toml stand in for their action's own key,
+        // though not while a prompt takes the typing
+        let key = match self.keymap.get(&key) {
+            Some(&action) if !prompting => action,
+            _ => key,
+        };
+        let key = match (self.mode, key) {
+            _ if !self.vim || prompting => key,
+            (Mode::Normal, Key::Char('j')) => Key::Down,
+            (Mode::Normal, Key::Char('k')) => Key::Up,
+            (Mode::Normal, Key::Char('g')) => Key::CtrlHome,
+            (Mode::Normal, Key::Char('G')) => Key::CtrlEnd,
+            (Mode::Normal, Key::Char('l')) => {
+                self.cursor = self.input.chars().count();
+                Key::Right
+            }
+            (Mode::Normal, Key::Char('h')) => {
+                self.input.clear();
+                Key::Backspace
+            }
+            (Mode::Normal, Key::Char('/')) => {
+                self.mode = Mode::Insert;
+                Key::Null
+            }
+            (Mode::Normal, Key::Char('\n' | '\t')) => key,
+            (Mode::Normal, Key::Char(_)) => Key::Null,
+            (Mode::Insert, Key::Esc) => {
+                self.mode = Mode::Normal;
+                Key::Null
+            }
+            _ => key,
+        };
+
+        // anything but more typing acts on the results, so they're
+        // matched against the query now rather than once it settles and
+        // the sort thread gets to it
+        let typing = match key {
+            Key::Char(ch) => !exclude_chars.contains(&ch),
+            Key::Backspace | Key::Delete | Key::Ctrl('u') | Key::Ctrl('w') => true,
+            _ => false,
+        };
+        if !typing && (self.match_at.take().is_some() || self.arrived.is_some()) {
+            self.abandon_sort();
+            let (pinned, dir) = (&self.pinned, &self.dir);
+            update_fuzz(
+                &mut self.output,
+                &mut self.unmatched,
+                &self.matcher,
+                &self.config.query(&self.input),
+                self.sort_mode,
+                |line| pinned.contains(&dir.join(&line.data)),
+            );
+            pin_to_top(&mut self.output, &self.dir, &self.pinned);
+            self.dropped |= cap_results(&mut self.output, self.config.max_results);
+        }
+
+        let event = if self.args_prompt.is_some() {
+            let mut event = None;
+
+            match key {
+                // restart fd with what was entered
+                Key::Char('\n') => {
+                    let typed = self.args_prompt.take().unwrap_or_default();
+
+                    let split = shell_words::split(&typed).map_err(|err| err.to_string());
+                    match split.and_then(|args| check_fd_args(&args).map(|()| args)) {
+                        Ok(args) => {
+                            self.prev_extra_args =
+                                Some(std::mem::replace(&mut self.extra_args, args));
+                            event = Some(AppEvent::Restart);
+                        }
+                        Err(err) => self.messages.notify(format!("bad fd arguments: {}", err)),
+                    }
+                }
+                Key::Esc | Key::Ctrl('c') => self.args_prompt = None,
+                Key::Backspace => {
+                    self.args_prompt.as_mut().map(String::pop);
+                }
+                Key::Char(ch) => {
+                    if let Some(typed) = self.args_prompt.as_mut() {
+                        typed.push(ch);
+                    }
+                }
+                _ => {}
+            }
+
+            clear_screen(&mut self.stdout)?;
+
+            event
+        } else if self.exclude_prompt.is_some() {
+            let mut event = None;
+
+            match key {
+                // add the pattern, or drop them all when it's empty
+                Key::Char('\n') => {
+                    let pattern = self.exclude_prompt.take().unwrap_or_default();
+                    if pattern.is_empty() {
+                        self.excludes.clear();
+                    } else {
+                        self.excludes.push(pattern);
+                    }
+                    event = Some(AppEvent::Restart);
+                }
+                Key::Esc | Key::Ctrl('c') => self.exclude_prompt = None,
+                Key::Backspace => {
+                    self.exclude_prompt.as_mut().map(String::pop);
+                }
+                Key::Char(ch) => {
+                    if let Some(typed) = self.exclude_prompt.as_mut() {
+                        typed.push(ch);
+                    }
+                }
+                _ => {}
+            }
+
+            clear_screen(&mut self.stdout)?;
+
+            event
+        } else if let Some(program) = self.exec_confirm.take() {
+            if key == Key::Char('y') {
+                let name = program.file_name().unwrap_or_default().to_string_lossy();
+
+                match run_program(
+                    &mut self.stdout,
+                    &program,
+                    &self.config.exec_args,
+                    &self.dir,
+                ) {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => self
+                        .messages
+                        .notify(format!("{} failed ({})", name, status)),
+                    Err(err) => self
+                        .messages
+                        .notify(format!("could not run {}: {}", name, err)),
+                }
+            }
+
+            clear_screen(&mut self.stdout)?;
+
+            None
+        } else if let Some(mut pending) = self.rename.take() {
+            let to = pending.from.with_file_name(&pending.name);
+            // whether to do the rename now, and whether the prompt is
+            // done with one way or another
+            let mut go = false;
+            let mut close = false;
+
+            match key {
+                // only overwrite what's there once that's been agreed to
+                Key::Char('y') if pending.confirm => go = true,
+                _ if pending.confirm => pending.confirm = false,
+                Key::Char('\n') => {
+                    if pending.name.is_empty() || pending.name.contains(std::path::is_separator) {
+                        self.messages
+                            .notify(format!("not a file name: {}", pending.name));
+                    } else if to == pending.from {
+                        close = true;
+                    } else if to.symlink_metadata().is_ok() {
+                        pending.confirm = true;
+                    } else {
+                        go = true;
+                    }
+                }
+                Key::Esc | Key::Ctrl('c') => close = true,
+                Key::Backspace => {
+                    pending.name.pop();
+                }
+                Key::Char(ch) => pending.name.push(ch),
+                _ => {}
+            }
+
+            if !go && !close {
+                self.rename = Some(pending);
+            } else if go {
+                let from = &pending.from;
+
+                match std::fs::rename(from, &to) {
+                    Ok(()) => {
+                        // swap the name in the entry where it is, so
+                        // the renamed entry stays the selected one
+                        let dir = &self.dir;
+                        let line = self
+                            .output
+                            .iter_mut()
+                            .find(|line| dir.join(&line.data) == *from);
+                        if let Some(line) = line {
+                            let mut data = Path::new(&line.data)
+                                .with_file_name(&pending.name)
+                                .to_string_lossy()
+                                .into_owned();
+                            if line.data.ends_with(std::path::is_separator) {
+                                data.push(std::path::MAIN_SEPARATOR);
+                            }
+
+                            *line = OutputLine::new(
+                                data,
+                                self.transform,
+                                self.config.fold_accents,
+                                &self.matcher,
+                                &self.config.query(&self.input),
+                            );
+                        }
+
+                        for pin in self.pinned.iter_mut().filter(|pin| *pin == from) {
+                            *pin = to.clone();
+                        }
+
+                        self.messages.notify(format!("renamed to {}", pending.name));
+                    }
+                    Err(err) => self.messages.notify(format!("could not rename: {}", err)),
+                }
+            }
+
+            clear_screen(&mut self.stdout)?;
+
+            None
+        } else {
+            match key {
+                // quit when ctrl + c is pressed
+                Key::Ctrl('c') => Some(AppEvent::Quit),
+                // and on ctrl + d like at a shell, though only with
+                // nothing typed so a stray one doesn't lose the query
+                Key::Ctrl('d') if self.input.is_empty() => Some(AppEvent::Quit),
+                // go to the selected bookmark
+                Key::Char('\n') if self.in_bookmarks => match self.output.get(self.selected) {
+                    Some(line) => {
+                        let bookmark = resolve_dir(self.config, Path::new(&line.data));
+                        if bookmark.is_none() {
+                            self.messages
+                                .notify(format!("not a directory: {}", line.data));
+                        } else {
+                            self.input.clear();
+                        }
+                        bookmark.map(AppEvent::Dir)
+                    }
+                    None => None,
+                },
+                // back to the directory from the bookmarks
+                Key::Esc if self.in_bookmarks => {
+                    self.input.clear();
+                    Some(AppEvent::Restart)
+                }
+                // Esc clears the query first and quits once there's
+                // nothing left to clear, so a second press exits
+                Key::Esc if !self.input.is_empty() => Some(AppEvent::Input(String::new())),
+                Key::Esc => Some(AppEvent::Quit),
+                // print the selected result's full path and quit, for
+                // `cd "$(fdi)"`
+                Key::Char('\n') => Some(AppEvent::Accept),
+                // mark the selected result to be printed along with the
+                // others marked, or unmark it
+                Key::Char('\t') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = result_path(&line.data, self.grep);
+                        let path: PathBuf = self.dir.join(path).components().collect();
+                        if !self.marked.remove(&path) {
+                            self.marked.insert(path);
+                        }
+                    }
+                    None
+                }
+                // print the selection, or everything marked, right away
+                // and stay open, for whatever reads fdi's output to take
+                // the picks one at a time
+                Key::Alt('\n') => {
+                    let paths: Vec<PathBuf> = if !self.marked.is_empty() {
+                        self.marked_sizes.clear();
+                        let mut paths: Vec<PathBuf> = self.marked.drain().collect();
+                        paths.sort();
+                        paths
+                    } else {
+                        self.output
+                            .get(self.selected)
+                            .map(|line| {
+                                self.dir
+                                    .join(result_path(&line.data, self.grep))
+                                    .components()
+                                    .collect()
+                            })
+                            .into_iter()
+                            .collect()
+                    };
+                    let count = paths.len();
+                    match self.sink.print(paths) {
+                        Ok(()) if count > 0 => {
+                            self.sent += count;
+                            self.messages.notify(match count {
+                                1 => "printed 1 path".to_string(),
+                                _ => format!("printed {} paths", count),
+                            });
+                            None
+                        }
+                        Ok(()) => None,
+                        Err(err) => Some(AppEvent::Error(format!("printing: {}", err))),
+                    }
+                }
+                // collect the selected result to print on the way out
+                // and keep going, or drop it from them again
+                Key::Alt('c') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = result_path(&line.data, self.grep);
+                        let path: PathBuf = self.dir.join(path).components().collect();
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        match self.collected.iter().position(|had| *had == path) {
+                            Some(nth) => {
+                                self.messages.notify(format!("dropped {}", name));
+                                self.collected.remove(nth);
+                            }
+                            None => {
+                                self.messages.notify(format!("collected {}", name));
+                                self.collected.push(path);
+                            }
+                        }
+                    }
+                    None
+                }
+                // until something has been listed there's nothing
+                // descending could mean on purpose
+                Key::Right
+                    if self.cursor == self.input.chars().count()
+                        && !self.config.early_enter
+                        && self.output.is_empty() =>
+                {
+                    None
+                }
+                // try to change directories with Right at the end of
+                // the query
+                Key::Right if self.cursor == self.input.chars().count() => {
+                    let typed = expand_path(&self.input);
+                    if let Some(input_dir) = resolve_dir(self.config, &self.dir.join(&typed)) {
+                        self.input.clear();
+                        Some(AppEvent::Dir(input_dir))
+                    } else {
+                        let mut event = None;
+
+                        for step in &self.config.on_bad_dir {
+                            match step {
+                                BadDir::Result => {
+                                    let result_dir = self
+                                        .output
+                                        .get(self.selected)
+                                        .filter(|line| line.matched)
+                                        .and_then(|line| {
+                                            resolve_dir(self.config, &self.dir.join(&line.data))
+                                        });
+
+                                    if let Some(result_dir) = result_dir {
+                                        if !self.config.keep_query {
+                                            self.input.clear();
+                                        }
+                                        event = Some(AppEvent::Dir(result_dir));
+                                        break;
+                                    }
+                                }
+                                BadDir::Message => {
+                                    let reason = why_not_dir(&self.dir.join(&typed));
+                                    self.messages.notify(format!("{}: {}", reason, self.input));
+                                    break;
+                                }
+                                BadDir::Flash => {
+                                    self.flash_input = true;
+                                    break;
+                                }
+                            }
+                        }
+
+                        event
+                    }
+                }
+                // zoom into the deepest directory shared by every match
+                Key::Alt('z') => {
+                    let matches = self
+                        .output
+                        .iter()
+                        .filter(|line| line.matched)
+                        .map(|line| Path::new(&line.data));
+
+                    if let Some(zoom_dir) = common_dir(&self.dir, matches) {
+                        if !self.config.zoom_keep_query && !self.config.keep_query {
+                            self.input.clear();
+                        }
+                        Some(AppEvent::Dir(zoom_dir))
+                    } else {
+                        None
+                    }
+                }
+                // list one more level when fd's depth is limited, or
+                // one less down to just the directory's own entries
+                Key::Alt('.') if self.config.from_file.is_none() => self.depth.map(|levels| {
+                    self.depth = Some(levels + 1);
+                    AppEvent::Restart
+                }),
+                Key::Alt(',') if self.config.from_file.is_none() => {
+                    // with no limit yet, go one above the deepest
+                    // entry listed so far
+                    let levels = self.depth.unwrap_or_else(|| {
+                        let deepest = self
+                            .output
+                            .iter()
+                            .chain(&self.unmatched)
+                            .map(|line| Path::new(&line.data).components().count())
+                            .max()
+                            .unwrap_or(1);
+                        deepest as u32
+                    });
+                    let shallower = Some(levels - 1).filter(|&levels| levels > 0);
+
+                    shallower.map(|levels| {
+                        self.depth = Some(levels);
+                        AppEvent::Restart
+                    })
+                }
+                // match the query against just the file names, or back
+                // against what --transform picks out
+                Key::Alt('b') => {
+                    self.transform = if self.transform == Transform::Basename {
+                        self.config.transform
+                    } else {
+                        Transform::Basename
+                    };
+                    self.messages.notify(
+                        match self.transform {
+                            Transform::Basename => "matching file names",
+                            Transform::Full => "matching whole paths",
+                            _ => "matching as --transform picks",
+                        }
+                        .to_string(),
+                    );
+
+                    self.output.append(&mut self.unmatched);
+                    for line in self.output.iter_mut() {
+                        *line = OutputLine::new(
+                            std::mem::take(&mut line.data),
+                            self.transform,
+                            self.config.fold_accents,
+                            &self.matcher,
+                            &self.config.query(&self.input),
+                        );
+                    }
+                    let (pinned, dir) = (&self.pinned, &self.dir);
+                    update_fuzz(
+                        &mut self.output,
+                        &mut self.unmatched,
+                        &self.matcher,
+                        &self.config.query(&self.input),
+                        self.sort_mode,
+                        |line| pinned.contains(&dir.join(&line.data)),
+                    );
+                    sort_output(
+                        &mut self.output,
+                        self.name_sort,
+                        self.sort_mode,
+                        &self.config.query(&self.input),
+                    );
+                    pin_to_top(&mut self.output, &self.dir, &self.pinned);
+                    self.dropped |= cap_results(&mut self.output, self.config.max_results);
+                    self.selected = 0;
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // list only directories, or everything again
+                Key::Alt('D') if self.config.from_file.is_none() => {
+                    self.fd_flags.types = if self.fd_flags.types == EntryType::Dirs {
+                        EntryType::All
+                    } else {
+                        EntryType::Dirs
+                    };
+                    Some(AppEvent::Restart)
+                }
+                // list the next kind of entries, round from everything
+                // through files, directories and symlinks
+                Key::Alt('T') if self.config.from_file.is_none() => {
+                    self.fd_flags.types = self.fd_flags.types.next();
+                    Some(AppEvent::Restart)
+                }
+                // list hidden files or leave them out from now on
+                Key::Alt('H') if self.config.from_file.is_none() => {
+                    self.fd_flags.hidden = !self.fd_flags.hidden;
+                    Some(AppEvent::Restart)
+                }
+                // follow symlinks or stop at them from now on
+                Key::Alt('F') if self.config.from_file.is_none() => {
+                    self.fd_flags.follow = !self.fd_flags.follow;
+                    Some(AppEvent::Restart)
+                }
+                // show the lines the ignore file hides, or hide them again
+                Key::Alt('I') if !self.ignores.is_empty() => {
+                    self.ignoring = !self.ignoring;
+                    if self.ignoring {
+                        self.output.append(&mut self.unmatched);
+                        let (hidden, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.output)
+                            .into_iter()
+                            .partition(|line| {
+                                !self.in_bookmarks && is_ignored(&self.ignores, &line.data)
+                            });
+                        self.output = kept;
+                        self.ignored
+                            .extend(hidden.into_iter().map(|line| line.data));
+                    } else {
+                        for line in self.ignored.drain(..) {
+                            self.unmatched.push(OutputLine::new(
+                                line,
+                                self.transform,
+                                self.config.fold_accents,
+                                &self.matcher,
+                                &self.config.query(&self.input),
+                            ));
+                        }
+                    }
+                    self.match_at = Some(Instant::now());
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // open a prompt for a pattern fd should leave out
+                Key::Alt('e') if self.config.from_file.is_none() => {
+                    self.exclude_prompt = Some(String::new());
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // open a prompt for extra arguments to run fd with
+                Key::Alt('a') if self.config.from_file.is_none() => {
+                    self.args_prompt = Some(shell_words::join(&self.extra_args));
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // redraw at the current terminal size, for terminals
+                // that didn't tell us about a resize
+                Key::Ctrl('l') => {
+                    let (width, height) = self.stdout.size()?;
+                    Some(AppEvent::Resize(width, height))
+                }
+                // list the current directory again for what's changed
+                // in it since, with the same entry selected once it's
+                // listed again
+                Key::Ctrl('r') if self.in_bookmarks => Some(AppEvent::Bookmarks),
+                Key::Ctrl('r') => {
+                    self.reselect = self.output.get(self.selected).map(|line| line.data.clone());
+                    Some(AppEvent::Restart)
+                }
+                // make the selected result's path the query to refine from
+                Key::Alt('i') => match self.output.get(self.selected) {
+                    Some(line) => {
+                        self.cursor = line.data.chars().count();
+                        Some(AppEvent::Input(line.data.clone()))
+                    }
+                    None => None,
+                },
+                // copy the selected result's full path
+                Key::Ctrl('y') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = self.dir.join(result_path(&line.data, self.grep));
+                        let path = path.to_string_lossy();
+
+                        self.messages.notify(match copy_to_clipboard(&path) {
+                            Ok(()) => format!("copied {}", path),
+                            Err(err) => format!("could not copy: {}", err),
+                        });
+                    }
+                    None
+                }
+                // copy just the file name of the selected result
+                Key::Alt('n') => {
+                    let name = self
+                        .output
+                        .get(self.selected)
+                        .and_then(|line| Path::new(result_path(&line.data, self.grep)).file_name())
+                        .map(|name| name.to_string_lossy().into_owned());
+
+                    if let Some(name) = name {
+                        self.messages.notify(match copy_to_clipboard(&name) {
+                            Ok(()) => format!("copied {}", name),
+                            Err(err) => format!("could not copy: {}", err),
+                        });
+                    }
+                    None
+                }
+                // edit the selected file in $EDITOR and come back after
+                Key::Ctrl('o') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = self.dir.join(result_path(&line.data, self.grep));
+                        // an rg hit opens at its line
+                        let at = grep_hit(&line.data).filter(|_| self.grep).map(|(_, at)| at);
+
+                        if path.is_file() {
+                            match edit_file(&mut self.stdout, &path, at, &self.dir) {
+                                Ok(status) if status.success() => {}
+                                Ok(status) => {
+                                    self.messages.notify(format!("editor failed ({})", status))
+                                }
+                                Err(err) => self
+                                    .messages
+                                    .notify(format!("could not edit {}: {}", line.data, err)),
+                            }
+                            clear_screen(&mut self.stdout)?;
+                        } else {
+                            self.messages.notify(format!("not a file: {}", line.data));
+                        }
+                    }
+                    None
+                }
+                // read all of the selected file in $PAGER, for when the
+                // preview's few lines aren't enough
+                Key::Alt('v') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = self.dir.join(result_path(&line.data, self.grep));
+
+                        if path.is_file() {
+                            match page_file(&mut self.stdout, &path, &self.dir) {
+                                Ok(status) if status.success() => {}
+                                Ok(status) => {
+                                    self.messages.notify(format!("pager failed ({})", status))
+                                }
+                                Err(err) => self
+                                    .messages
+                                    .notify(format!("could not page {}: {}", line.data, err)),
+                            }
+                            clear_screen(&mut self.stdout)?;
+                        } else {
+                            self.messages.notify(format!("not a file: {}", line.data));
+                        }
+                    }
+                    None
+                }
+                // open the selected result with the default application
+                Key::Alt('o') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        if let Err(err) =
+                            open_path(&self.dir.join(result_path(&line.data, self.grep)))
+                        {
+                            self.messages
+                                .notify(format!("could not open {}: {}", line.data, err));
+                        }
+                    }
+                    None
+                }
+                // match the query as a plain substring, or back the way
+                // it was matched before, once the query's matched again
+                Key::Ctrl('e') => {
+                    self.algo = match self.algo {
+                        Algo::Exact if self.config.algo == Algo::Exact => Algo::Skim,
+                        Algo::Exact => self.config.algo,
+                        _ => Algo::Exact,
+                    };
+                    self.matcher = Arc::new(Matcher {
+                        scorer: self.algo.scorer(self.config.case),
+                        min_score: self.matcher.min_score,
+                    });
+                    self.match_at = Some(Instant::now());
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // go on to the next way of ordering the results, which
+                // outlasts query changes unlike the one-off name sort
+                Key::Alt('S') => {
+                    self.sort_mode = self.sort_mode.next();
+                    self.name_sort = None;
+                    sort_output(
+                        &mut self.output,
+                        self.name_sort,
+                        self.sort_mode,
+                        &self.config.query(&self.input),
+                    );
+                    pin_to_top(&mut self.output, &self.dir, &self.pinned);
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // sort the results by name, flipping the order on repeat
+                Key::Alt('s') => {
+                    self.name_sort = match self.name_sort {
+                        Some(NameSort::Ascending) => Some(NameSort::Descending),
+                        _ => Some(NameSort::Ascending),
+                    };
+                    sort_output(
+                        &mut self.output,
+                        self.name_sort,
+                        self.sort_mode,
+                        &self.config.query(&self.input),
+                    );
+                    pin_to_top(&mut self.output, &self.dir, &self.pinned);
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // swap back to the previous directory like `cd -`
+                Key::Alt('-') => {
+                    if self.prev_dir.is_some() && !self.config.keep_query {
+                        self.input.clear();
+                    }
+                    self.prev_dir.clone().map(AppEvent::Dir)
+                }
+                // bookmark the current directory
+                Key::Ctrl('b') => {
+                    self.messages.notify(match add_bookmark(&self.dir) {
+                        Ok(true) => format!("bookmarked {}", self.dir.display()),
+                        Ok(false) => format!("already bookmarked {}", self.dir.display()),
+                        Err(err) => format!("could not bookmark: {}", err),
+                    });
+                    None
+                }
+                // search the contents of the files for the query with
+                // rg, or go back to listing their names
+                Key::Alt('g') => {
+                    self.grep = !self.grep;
+                    Some(AppEvent::Restart)
+                }
+                // list the bookmarks to pick one to go to, or go back
+                // to the directory
+                Key::Alt('B') => {
+                    self.input.clear();
+                    Some(if self.in_bookmarks {
+                        AppEvent::Restart
+                    } else {
+                        AppEvent::Bookmarks
+                    })
+                }
+                // step back and forward through the directories visited
+                Key::AltLeft | Key::AltRight => self.history.step(key == Key::AltLeft).map(|to| {
+                    if !self.config.keep_query {
+                        self.input.clear();
+                    }
+                    AppEvent::Dir(to)
+                }),
+                // move the selection, a result or a page of them, which
+                // at the bottom header goes up through the results as
+                // they grow upwards
+                Key::Up | Key::Down | Key::PageUp | Key::PageDown => {
+                    let down = key == Key::Down || key == Key::PageDown;
+                    let towards_worse = match self.config.header {
+                        HeaderPosition::Top => down,
+                        HeaderPosition::Bottom => !down,
+                    };
+                    let step = if key == Key::PageUp || key == Key::PageDown {
+                        let edge = u16::from(self.border.is_some());
+                        let rows = self
+                            .config
+                            .header
+                            .rows(self.term_height)
+                            .region_rows
+                            .saturating_sub(2 * edge);
+                        i32::from(rows.max(1))
+                    } else {
+                        1
+                    };
+                    Some(AppEvent::Move(if towards_worse { step } else { -step }))
+                }
+                // jump to the best result or the worst, since Home and
+                // End move the cursor in the query
+                Key::CtrlHome | Key::Alt('<') => Some(AppEvent::Move(i32::MIN)),
+                Key::CtrlEnd | Key::Alt('>') => Some(AppEvent::Move(i32::MAX)),
+                // switch between wrapping and cutting off long results
+                Key::Alt('w') if self.config.allow_wrap => {
+                    self.wrap = !self.wrap;
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // show or hide the preview of the selected result
+                Key::Ctrl('p') => {
+                    self.preview = !self.preview;
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // scroll the preview a line at a time, not past the
+                // last line of it
+                Key::Ctrl('j') if self.preview => {
+                    if self
+                        .preview_cache
+                        .as_ref()
+                        .is_some_and(|cache| cache.4.len() > 1)
+                    {
+                        self.preview_scroll += 1;
+                    }
+                    None
+                }
+                Key::Ctrl('k') if self.preview => {
+                    self.preview_scroll = self.preview_scroll.saturating_sub(1);
+                    None
+                }
+                // keep the preview scrolled where it is for the next
+                // results selected, or go back to following them
+                Key::Alt('P') => {
+                    self.preview_locked = !self.preview_locked;
+                    self.messages.notify(if self.preview_locked {
+                        "preview scroll locked".to_string()
+                    } else {
+                        "preview follows the selection".to_string()
+                    });
+                    None
+                }
+                // show or hide what Enter would print for the selection
+                Key::Alt('q') => {
+                    self.show_output = !self.show_output;
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // show or hide the size and age column
+                Key::Alt('l') => {
+                    self.metadata = !self.metadata;
+                    clear_screen(&mut self.stdout)?;
+                    None
+                }
+                // pin the selected result to the top, or unpin it, and
+                // keep it selected wherever that puts it
+                Key::Alt('p') => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let path = self.dir.join(&line.data);
+
+                        if let Some(nth) = self.pinned.iter().position(|pin| *pin == path) {
+                            self.pinned.remove(nth);
+                            let (pinned, dir) = (&self.pinned, &self.dir);
+                            set_aside_unmatched(&mut self.output, &mut self.unmatched, |line| {
+                                pinned.contains(&dir.join(&line.data))
+                            });
+                            sort_output(
+                                &mut self.output,
+                                self.name_sort,
+                                self.sort_mode,
+                                &self.config.query(&self.input),
+                            );
+                        } else {
+                            self.pinned.push(path.clone());
+                        }
+                        pin_to_top(&mut self.output, &self.dir, &self.pinned);
+
+                        self.selected = self
+                            .output
+                            .iter()
+                            .position(|line| self.dir.join(&line.data) == path)
+                            .unwrap_or(0);
+                        clear_screen(&mut self.stdout)?;
+                    }
+                    None
+                }
+                // unpin everything
+                Key::Alt('u') => {
+                    if !self.pinned.is_empty() {
+                        self.messages
+                            .notify(format!("unpinned {}", self.pinned.len()));
+                        self.pinned.clear();
+                        set_aside_unmatched(&mut self.output, &mut self.unmatched, |_| false);
+                        sort_output(
+                            &mut self.output,
+                            self.name_sort,
+                            self.sort_mode,
+                            &self.config.query(&self.input),
+                        );
+                        clear_screen(&mut self.stdout)?;
+                    }
+                    None
+                }
+                // run the selected result, once it's been confirmed
+                Key::Alt('x') if self.config.allow_exec => {
+                    if let Some(line) = self.output.get(self.selected) {
+                        let program = self.dir.join(&line.data);
+
+                        if is_executable(&program) {
+                            self.exec_confirm = Some(program);
+                        } else {
+                            self.messages
+                                .notify(format!("not executable: {}", line.data));
+                        }
+                        clear_screen(&mut self.stdout)?;
+                    }
+                    None
+                }
+                // rename the selected result in place
+                Key::Alt(c) if c == self.config.rename_key => {
+                    let entry = self.output.get(self.selected).and_then(|line| {
+                        let name = Path::new(&line.data).file_name()?;
+                        Some((
+                            self.dir.join(&line.data),
+                            name.to_string_lossy().into_owned(),
+                        ))
+                    });
+
+                    if let Some((from, name)) = entry {
+                        self.rename = Some(Rename {
+                            from,
+                            name,
+                            confirm: false,
+                        });
+                        clear_screen(&mut self.stdout)?;
+                    }
+                    None
+                }
+                // jump to one of the --jump directories
+                Key::Alt(c) if self.config.jumps.iter().any(|(key, _)| *key == c) => {
+                    let (_, path) = self.config.jumps.iter().find(|(key, _)| *key == c).unwrap();
+
+                    match resolve_dir(self.config, path) {
+                        Some(jump_dir) => {
+                            if !self.config.keep_query {
+                                self.input.clear();
+                            }
+                            Some(AppEvent::Dir(jump_dir))
+                        }
+                        None => {
+                            self.messages
+                                .notify(format!("not a directory: {}", path.display()));
+                            None
+                        }
+                    }
+                }
+                // handle keyboard input
+                Key::Char(ch) => {
+                    let exclude = exclude_chars.iter().find(|&ex| *ex == ch);
+
+                    if exclude.is_none() {
+                        let mut pattern = self.input.clone();
+                        pattern.insert(byte_offset(&self.input, self.cursor), ch);
+                        self.cursor += 1;
+                        Some(AppEvent::Input(pattern))
+                    } else {
+                        None
+                    }
+                }
+                // clear the whole query, staying in the directory
+                Key::Ctrl('u') if !self.input.is_empty() => Some(AppEvent::Input(String::new())),
+                // delete the word before the cursor
+                Key::Ctrl('w') if self.cursor > 0 => {
+                    let (before, after) =
+                        self.input.split_at(byte_offset(&self.input, self.cursor));
+                    let before = delete_word(before);
+                    self.cursor = before.chars().count();
+                    Some(AppEvent::Input(format!("{}{}", before, after)))
+                }
+                // move the cursor through the query
+                Key::Left => {
+                    self.cursor = self.cursor.saturating_sub(1);
+                    None
+                }
+                Key::Right => {
+                    self.cursor += 1;
+                    None
+                }
+                Key::Home => {
+                    self.cursor = 0;
+                    None
+                }
+                Key::End => {
+                    self.cursor = self.input.chars().count();
+                    None
+                }
+                // delete the char under the cursor
+                Key::Delete if self.cursor < self.input.chars().count() => {
+                    clear_screen(&mut self.stdout)?;
+                    Some(AppEvent::Input(remove_char(&self.input, self.cursor)))
+                }
+                // handle the backspace
+                Key::Backspace => {
+                    let mut event = None;
+
+                    if self.input.is_empty() {
+                        // go up to the parent directory
+                        if let Some(parent_dir) = self.dir.parent() {
+                            event = Some(AppEvent::Dir(PathBuf::from(parent_dir)));
+                        }
+                    } else if self.cursor > 0 {
+                        // drop the char before the cursor, however many
+                        // bytes it takes
+                        self.cursor -= 1;
+                        event = Some(AppEvent::Input(remove_char(&self.input, self.cursor)));
+                    }
+
+                    // Make sure the screen gets a full clear when the backspace happens
+                    clear_screen(&mut self.stdout)?;
+
+                    event
+                }
+                _ => None,
+            }
+        };
+
+        Ok(event)
+    }
+
+    /// What a line of --control asks for, as if it were typed
+    fn control(&mut self, line: &str) -> Option<AppEvent> {
+        match Control::parse(line) {
+            Ok(Control::Cd(to)) => match resolve_dir(self.config, &self.dir.join(expand_path(&to)))
+            {
+                Some(to) => {
+                    if !self.config.keep_query {
+                        self.input.clear();
+                    }
+                    Some(AppEvent::Dir(to))
+                }
+                None => Some(AppEvent::Error(format!("not a directory: {}", to))),
+            },
+            Ok(Control::Query(query)) => {
+                self.cursor = query.chars().count();
+                Some(AppEvent::Input(query))
+            }
+            Err(err) => Some(AppEvent::Error(format!("--control: {}", err))),
+        }
+    }
+
+    /// Take in a `batch` of lines of the listing, sorting the results
+    /// once for all of it
+    fn take_lines(&mut self, batch: Vec<String>) {
+        let mut added = false;
+        for line in batch {
+            let line = self.config.trim.apply(line);
+            let line = match self.config.from_file {
+                Some(_) if !self.in_bookmarks && !self.grep => {
+                    list_entry(self.base_dir, &self.dir, line)
+                }
+                _ => Some(line),
+            };
+            let line = line.filter(|line| {
+                self.config.keep_root
+                    || self.in_bookmarks
+                    || self.grep
+                    || !is_dir_itself(&self.dir, line)
+            });
+            let line = line.filter(|line| !self.config.dedup || self.seen.insert(line.clone()));
+
+            if let Some(line) = line {
+                if self.ignoring && !self.in_bookmarks && is_ignored(&self.ignores, &line) {
+                    self.ignored.push(line);
+                    continue;
+                }
+
+                let line = OutputLine::new(
+                    line,
+                    self.transform,
+                    self.config.fold_accents,
+                    &self.matcher,
+                    &self.config.query(&self.input),
+                );
+
+                if let Some(arrived) = &mut self.arrived {
+                    arrived.push(line.clone());
+                }
+                added |= self.keep_line(line);
+            }
+        }
+
+        if added {
+            sort_output(
+                &mut self.output,
+                self.name_sort,
+                self.sort_mode,
+                &self.config.query(&self.input),
+            );
+            pin_to_top(&mut self.output, &self.dir, &self.pinned);
+            self.dropped |= cap_results(&mut self.output, self.config.max_results);
+
+            // follow the entry being selected again as later
+            // lines sort in around it
+            if let Some(name) = &self.reselect {
+                if let Some(nth) = self.output.iter().position(|line| line.data == *name) {
+                    self.selected = nth;
+                }
+            }
+        }
+    }
+
+    /// Put `line` with the results if it matches or is pinned, or else
+    /// aside, dropping it instead when the results are capped. Whether
+    /// it's one of the results
+    fn keep_line(&mut self, line: OutputLine) -> bool {
+        if line.matched || self.pinned.contains(&self.dir.join(&line.data)) {
+            self.output.push(line);
+            return true;
+        }
+
+        if self.config.max_results.is_some() {
+            self.dropped = true;
+        } else {
+            self.unmatched.push(line);
+        }
+        false
+    }
+
+    /// Do what's come due since the last time round the main loop, for
+    /// the listing that finished and the listing or matching waiting to
+    /// be done, returning the events that calls for
+    pub async fn tick(&mut self) -> Result<Vec<AppEvent>, Box<dyn Error>> {
+        let mut events = Vec::new();
+
+        // once fd is done check whether it took newly entered arguments,
+        // going back to the previous ones if it didn't, and whether it
+        // failed before listing anything and should get another go, or
+        // else the user should hear it failed
+        if let Some(status) = self
+            .source
+            .status
+            .as_mut()
+            .and_then(|rx| rx.try_recv().ok())
+        {
+            self.source.status = None;
+            self.redraw = true;
+
+            if let Some(prev_args) = self.prev_extra_args.take() {
+                if !status.success() {
+                    self.messages.notify(format!(
+                        "fd rejected {} ({})",
+                        shell_words::join(&self.extra_args),
+                        status
+                    ));
+                    self.extra_args = prev_args;
+                    events.push(AppEvent::Restart);
+                }
+            } else if !status.success() && !self.grep {
+                if self.output.is_empty() && self.fd_retries < self.config.fd_retries {
+                    self.fd_retries += 1;
+                    self.retry_at =
+                        Some(Instant::now() + Duration::from_millis(100 << self.fd_retries));
+                } else if self.fd_retries > 0 {
+                    events.push(AppEvent::Error(format!(
+                        "{} failed {} times ({})",
+                        self.lister,
+                        self.fd_retries + 1,
+                        status
+                    )));
+                } else {
+                    // like for a directory it couldn't read, which leaves
+                    // the listing short
+                    events.push(AppEvent::Error(match status.code() {
+                        Some(code) => format!("{} exited with code {}", self.lister, code),
+                        None => format!("{} exited ({})", self.lister, status),
+                    }));
+                }
+            }
+        }
+
+        // rg is run again for a new query, and so is fd when lines it
+        // listed were dropped, where otherwise the listing is only matched
+        // against it again
+        if (self.grep || self.dropped) && self.match_at.is_some_and(|at| Instant::now() >= at) {
+            self.match_at = None;
+            events.push(AppEvent::Restart);
+        }
+        // the results are matched on the sort thread, and drawn as they
+        // were until they're back
+        if self.match_at.is_some_and(|at| Instant::now() >= at) {
+            self.match_at = None;
+            self.arrived = Some(Vec::new());
+
+            let _ = self.sorter.send(SortJob {
+                generation: self.matching.next(),
+                output: self.output.clone(),
+                unmatched: self.unmatched.clone(),
+                matcher: Arc::clone(&self.matcher),
+                pattern: self.config.query(&self.input),
+                sort_mode: self.sort_mode,
+                pinned: self.pinned.clone(),
+                dir: self.dir.clone(),
+            });
+        }
+
+        if self.settle_at.is_some_and(|at| Instant::now() >= at) {
+            self.settle_at = None;
+            let source = open_source(
+                self.config,
+                &self.dir,
+                self.depth,
+                self.fd_flags,
+                &self.excludes,
+                &self.extra_args,
+                self.grep.then_some(self.input.as_str()),
+            )
+            .await?;
+            self.listen(source);
+        }
+
+        if self.retry_at.is_some_and(|at| Instant::now() >= at) {
+            self.retry_at = None;
+            self.output.clear();
+            self.unmatched.clear();
+            self.ignored.clear();
+            self.dropped = false;
+            self.seen.clear();
+            let source = open_source(
+                self.config,
+                &self.dir,
+                self.depth,
+                self.fd_flags,
+                &self.excludes,
+                &self.extra_args,
+                self.grep.then_some(self.input.as_str()),
+            )
+            .await?;
+            self.listen(source);
+        }
+
+        // a sink that's gone or has no reader just misses this batch
+        if self.mirror_at.is_some_and(|at| Instant::now() >= at) {
+            self.mirror_at = None;
+
+            if let Some(mirror) = &self.config.mirror {
+                let top: Vec<PathBuf> = self
+                    .output
+                    .iter()
+                    .take(self.config.mirror_count)
+                    .map(|line| self.dir.join(&line.data))
+                    .collect();
+                let _ = write_mirror(mirror, &top);
+            }
+        }
+
+        // a spinner while a listing that's taking a while is still going
+        let loading = self.source.started.elapsed();
+        let spinner = Some((loading.as_millis() / 100 % 4) as usize).filter(|_| {
+            (self.source.status.is_some() || self.settle_at.is_some())
+                && loading >= self.config.loading_grace
+        });
+        if spinner != self.last_spinner {
+            self.last_spinner = spinner;
+            self.redraw = true;
+        }
+
+        if self.messages.expire() {
+            clear_screen(&mut self.stdout)?;
+            self.redraw = true;
+        }
+
+        Ok(events)
+    }
+
+    /// Draw once something's changed, no more than once a frame
+    pub fn draw(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.redraw || self.drawn_at.is_some_and(|at| at.elapsed() < FRAME_TIME) {
+            return Ok(());
+        }
+        self.redraw = false;
+        self.drawn_at = Some(Instant::now());
+
+        self.render()
+    }
+
+    /// Draw the prompt, the status line and the results as they are
+    fn render(&mut self) -> Result<(), Box<dyn Error>> {
+        self.result_rows.clear();
+
+        // with no results say why, but not before the listing's done and
+        // there could still be some
+        let placeholder = if !self.output.is_empty()
+            || self.source.status.is_some()
+            || self.settle_at.is_some()
+        {
+            None
+        } else if self.unmatched.is_empty() && self.ignored.is_empty() {
+            Some("(empty)")
+        } else {
+            Some("no matches")
+        };
+        if placeholder != self.last_placeholder {
+            if self.last_placeholder.is_some() {
+                clear_screen(&mut self.stdout)?;
+            }
+            self.last_placeholder = placeholder;
+        }
+
+        // too narrow for the results to be any use, so just keep the
+        // query visible, its end if it doesn't fit
+        if self.term_width < self.config.min_width {
+            let ScreenRows {
+                prompt: prompt_row,
+                status: status_row,
+                ..
+            } = self.config.header.rows(self.term_height);
+            let width = self.term_width as usize;
+            let typed = self
+                .args_prompt
+                .as_ref()
+                .or(self.exclude_prompt.as_ref())
+                .or(self.rename.as_ref().map(|pending| &pending.name))
+                .unwrap_or(&self.input);
+            // room for the cursor after the end too
+            let skip = (typed.chars().count() + 3).saturating_sub(width);
+            let at = if self.args_prompt.is_none()
+                && self.exclude_prompt.is_none()
+                && self.rename.is_none()
+            {
+                self.cursor = self.cursor.min(self.input.chars().count());
+                self.cursor
+            } else {
+                typed.chars().count()
+            };
+            let visible: String = typed.chars().skip(skip).collect();
+
+            write!(
+                self.stdout,
+                "{}{}{}> {}",
+                goto(1, status_row),
+                "widen window".chars().take(width).collect::<String>(),
+                goto(1, prompt_row),
+                with_cursor(&visible, at.saturating_sub(skip))
+            )?;
+        } else {
+            // the results region sits below the prompt and status lines, or
+            // above them with the header at the bottom, and the results are
+            // inset from its edges by the border and padding
+            let ScreenRows {
+                prompt: prompt_row,
+                status: status_row,
+                region_top,
+                region_rows,
+            } = self.config.header.rows(self.term_height);
+            // the preview takes the right half or the top half, as long as
+            // both halves are still big enough to be any use
+            let show_preview = self.preview
+                && match self.config.preview_position {
+                    PreviewPosition::Right => self.term_width / 2 >= self.config.min_width,
+                    PreviewPosition::Top => region_rows / 2 >= MIN_PREVIEW_ROWS,
+                };
+            let list_width =
+                if show_preview && self.config.preview_position == PreviewPosition::Right {
+                    self.term_width / 2
+                } else {
+                    self.term_width
+                };
+            // above the results the preview's rows come out of their region
+            let pane_rows = if show_preview && self.config.preview_position == PreviewPosition::Top
+            {
+                region_rows / 2
+            } else {
+                0
+            };
+            let pane_top = region_top;
+            let (region_top, region_rows) = (region_top + pane_rows, region_rows - pane_rows);
+            let edge = u16::from(self.border.is_some());
+            let inset = edge + self.config.padding;
+            let results_rows = region_rows.saturating_sub(2 * edge);
+            let results_width = list_width.saturating_sub(2 * inset);
+            self.list_columns = list_width;
+            // the size and age column comes out of the results' room, as
+            // long as that leaves them some
+            let meta_width = if self.metadata && results_width >= 2 * METADATA_WIDTH {
+                METADATA_WIDTH
+            } else {
+                0
+            };
+
+            // the results can shrink under the selection, and it's kept on
+            // screen assuming a row per result
+            (self.selected, self.scroll) = keep_in_view(
+                self.selected,
+                self.scroll,
+                self.output.len(),
+                results_rows as usize,
+            );
+
+            // which chars matched is only worked out for the results
+            // about to be drawn, ranking them needs no more than scores
+            let end = (self.scroll + results_rows as usize).min(self.output.len());
+            let pattern = self.config.query(&self.input);
+            for line in &mut self.output[self.scroll.min(end)..end] {
+                line.highlight(&self.matcher, &pattern);
+            }
+
+            let shown = &self.output[self.scroll.min(self.output.len())..];
+            let visible = &shown[..shown.len().min(results_rows as usize)];
+            // only the shown results get stat'ed for whether they're
+            // directories, and the slash is drawn in the same style as
+            // the rest of the name
+            let slashed: Vec<OutputLine>;
+            let visible = if self.config.dir_slash && !self.grep {
+                slashed = visible
+                    .iter()
+                    .map(|line| {
+                        let mut line = line.clone();
+                        if !line.data.ends_with(std::path::is_separator)
+                            && self.dir.join(&line.data).is_dir()
+                        {
+                            line.data.push(std::path::MAIN_SEPARATOR);
+                        }
+                        line
+                    })
+                    .collect();
+                &slashed[..]
+            } else {
+                visible
+            };
+            // full paths still join onto `dir` as themselves below
+            let absolute: Vec<OutputLine>;
+            let visible = if self.config.display_paths == PathStyle::Absolute {
+                absolute = visible.iter().map(|line| line.under(&self.dir)).collect();
+                &absolute[..]
+            } else {
+                visible
+            };
+            let options = DisplayOptions {
+                width: (results_width - meta_width) as usize,
+                dim: if self.config.dim_prefix {
+                    common_prefix_len(visible.iter().map(|line| line.data.as_str()))
+                } else {
+                    0
+                },
+                home: self.home.as_deref(),
+                max_runs: self.config.max_color_runs,
+                pattern: &self.input,
+                case_shade: self.config.case_shade,
+                ellipsis: self.config.ellipsis,
+                theme: self.theme,
+                base: None,
+                color: self.color,
+            };
+
+            // output the up to the term height of
+            // lines from the command output, a row each or as many as
+            // they wrap onto
+            let mut used = 0;
+            for (nth_line, line) in visible.iter().enumerate() {
+                let rows_left = (results_rows - used) as usize;
+                if rows_left == 0 {
+                    break;
+                }
+
+                // pinned results are marked, giving up the marker's room
+                let path = self.dir.join(&line.data);
+                let pin = self.pinned.contains(&path);
+                let mark = self.marked.contains(
+                    &self
+                        .dir
+                        .join(result_path(&line.data, self.grep))
+                        .components()
+                        .collect::<PathBuf>(),
+                );
+                let style = self.ls_colors.as_ref().and_then(|colors| {
+                    colors.style(&self.dir.join(result_path(&line.data, self.grep)))
+                });
+                let options = DisplayOptions {
+                    width: options
+                        .width
+                        .saturating_sub(if pin || mark { 2 } else { 0 }),
+                    base: style,
+                    ..options
+                };
+                let rows = if self.wrap {
+                    line.display_rows(&options, rows_left)
+                } else {
+                    vec![line.display(&options)]
+                };
 
-        if let Some((fscore, findices)) = matcher.fuzzy_indices(&data, &match_with) {
-            score = fscore;
-            indices = findices;
+                // only the shown results get stat'ed
+                let meta = Some(meta_width)
+                    .filter(|&width| width > 0)
+                    .map(|_| metadata_column(&self.dir.join(result_path(&line.data, self.grep))));
+
+                let count = rows.len() as u16;
+                for (nth, text) in rows.into_iter().enumerate() {
+                    let nth = nth as u16;
+                    // the rows of a line read downwards both ways, so at
+                    // the bottom its first row goes at the top of its rows
+                    let row = match self.config.header {
+                        HeaderPosition::Top => region_top + edge + used + nth,
+                        HeaderPosition::Bottom => {
+                            region_top + region_rows - edge - used - count + nth
+                        }
+                    };
+                    let marker = match (pin, mark, nth) {
+                        (false, false, _) => "",
+                        (_, _, 1..) => "  ",
+                        (true, false, 0) => "* ",
+                        (false, true, 0) => " +",
+                        (true, true, 0) => "*+",
+                    };
+                    self.result_rows.push((row, self.scroll + nth_line));
+                    // without color the selected row is drawn in reverse
+                    // video, the one thing still telling it apart
+                    let (background, background_off) = if self.scroll + nth_line != self.selected {
+                        (String::new(), String::new())
+                    } else if self.color {
+                        (
+                            SetBackgroundColor(Color::AnsiValue(self.theme.selected)).to_string(),
+                            SetBackgroundColor(Color::Reset).to_string(),
+                        )
+                    } else {
+                        (
+                            SetAttribute(Attribute::Reverse).to_string(),
+                            SetAttribute(Attribute::NoReverse).to_string(),
+                        )
+                    };
+
+                    write!(
+                        self.stdout,
+                        "{}{}{}{}{}",
+                        goto(1 + inset, row),
+                        background,
+                        marker,
+                        text,
+                        background_off
+                    )?;
+
+                    if let Some(meta) = meta.as_ref().filter(|_| nth == 0) {
+                        let (grey, grey_off) = if self.color {
+                            (
+                                SetForegroundColor(Color::DarkGrey).to_string(),
+                                SetForegroundColor(Color::Reset).to_string(),
+                            )
+                        } else {
+                            (String::new(), String::new())
+                        };
+                        write!(
+                            self.stdout,
+                            "{}{}{}{}{}{}",
+                            goto(1 + inset + results_width - meta_width, row),
+                            background,
+                            grey,
+                            meta,
+                            grey_off,
+                            background_off
+                        )?;
+                    }
+                }
+                used += count;
+            }
+
+            if let Some(text) = placeholder.filter(|_| results_rows > 0) {
+                let text: String = text.chars().take(results_width as usize).collect();
+                let row = region_top + edge + (results_rows - 1) / 2;
+                let column = 1 + inset + (results_width - text.len() as u16) / 2;
+                let (style, style_off) = if self.color {
+                    (
+                        SetForegroundColor(Color::DarkGrey).to_string(),
+                        SetForegroundColor(Color::Reset).to_string(),
+                    )
+                } else {
+                    (
+                        SetAttribute(Attribute::Dim).to_string(),
+                        SetAttribute(Attribute::NormalIntensity).to_string(),
+                    )
+                };
+                write!(
+                    self.stdout,
+                    "{}{}{}{}",
+                    goto(column, row),
+                    style,
+                    text,
+                    style_off
+                )?;
+            }
+
+            if let Some(border) = self.border {
+                if region_rows >= 2 {
+                    draw_border(
+                        &mut self.stdout,
+                        border,
+                        region_top,
+                        region_top + region_rows - 1,
+                        list_width,
+                    )?;
+                }
+            }
+
+            if show_preview {
+                // above the results its last row is a rule between them
+                let (pane_col, pane_top, pane_width, rows) = match self.config.preview_position {
+                    PreviewPosition::Right => (
+                        list_width + 2,
+                        region_top,
+                        self.term_width.saturating_sub(list_width + 1) as usize,
+                        region_rows as usize,
+                    ),
+                    PreviewPosition::Top => (
+                        1,
+                        pane_top,
+                        self.term_width as usize,
+                        pane_rows as usize - 1,
+                    ),
+                };
+
+                let lines = match self.output.get(self.selected) {
+                    Some(line) => {
+                        let path = self.dir.join(result_path(&line.data, self.grep));
+                        // an rg hit is shown with a few lines before it
+                        if self.preview_for.as_ref() != Some(&path) && !self.preview_locked {
+                            self.preview_scroll = grep_hit(&line.data)
+                                .filter(|_| self.grep)
+                                .map_or(0, |(_, at)| (at as usize).saturating_sub(1 + rows / 3));
+                        }
+                        self.preview_for = Some(path.clone());
+                        let cached = self.preview_cache.as_ref().is_some_and(|cache| {
+                            cache.0 == path
+                                && cache.1 == self.preview_scroll
+                                && cache.2 == rows
+                                && cache.3 == pane_width
+                        });
+                        if !cached {
+                            let lines = preview_lines(&path, self.preview_scroll, rows, pane_width);
+                            self.preview_cache =
+                                Some((path, self.preview_scroll, rows, pane_width, lines));
+                        }
+                        self.preview_cache
+                            .as_ref()
+                            .map_or(&[][..], |cache| &cache.4[..])
+                    }
+                    None => &[][..],
+                };
+
+                for nth in 0..rows as u16 {
+                    write!(
+                        self.stdout,
+                        "{}{}{}",
+                        goto(pane_col, pane_top + nth),
+                        lines.get(nth as usize).map_or("", |line| line.as_str()),
+                        Clear(ClearType::UntilNewLine)
+                    )?;
+                }
+                if self.config.preview_position == PreviewPosition::Top {
+                    write!(
+                        self.stdout,
+                        "{}{}",
+                        goto(1, pane_top + rows as u16),
+                        "─".repeat(pane_width)
+                    )?;
+                }
+            }
+
+            // how many lines the query matches out of all that were listed,
+            // or were kept when --max-results dropped some
+            let total = self.output.len() + self.unmatched.len();
+            let results = self.output.iter().filter(|line| line.matched).count();
+            write!(self.stdout, "{} {}/{}", goto(1, status_row), results, total)?;
+            if self.dropped {
+                write!(self.stdout, "+")?;
+            }
+
+            // so it's clear what raising --min-score cost
+            if self.config.show_below {
+                let below = self
+                    .unmatched
+                    .iter()
+                    .filter(|line| line.below_min_score)
+                    .count();
+                if below > 0 {
+                    write!(self.stdout, " ({} below threshold)", below)?;
+                }
+            }
+
+            if !self.ignored.is_empty() {
+                write!(self.stdout, "  {} ignored", self.ignored.len())?;
+            } else if !self.ignores.is_empty() && !self.ignoring {
+                write!(self.stdout, "  not ignoring")?;
+            }
+
+            if !self.collected.is_empty() {
+                write!(self.stdout, "  {} collected", self.collected.len())?;
+            }
+
+            // directories count for nothing, adding up what's in them
+            // could take as long as listing them
+            if self.config.marked_size && !self.marked.is_empty() {
+                let sizes = &mut self.marked_sizes;
+                let total: u64 = self
+                    .marked
+                    .iter()
+                    .map(|path| {
+                        *sizes.entry(path.clone()).or_insert_with(|| {
+                            path.metadata()
+                                .ok()
+                                .filter(|meta| meta.is_file())
+                                .map_or(0, |meta| meta.len())
+                        })
+                    })
+                    .sum();
+                write!(
+                    self.stdout,
+                    "  {} marked {}",
+                    self.marked.len(),
+                    human_size(total)
+                )?;
+            }
+
+            if self.wrap {
+                write!(self.stdout, "  wrap")?;
+            }
+
+            if self.algo == Algo::Exact {
+                write!(self.stdout, "  exact")?;
+            }
+
+            // none of fd's settings mean anything for a list
+            let listing = self.config.from_file.is_none() && !self.in_bookmarks;
+
+            if let Some(levels) = self.depth.filter(|_| listing) {
+                write!(self.stdout, "  depth {}", levels)?;
+            }
+
+            if listing {
+                write!(
+                    self.stdout,
+                    "  {}",
+                    if self.fd_flags.hidden {
+                        "hidden"
+                    } else {
+                        "no hidden"
+                    }
+                )?;
+
+                match self.fd_flags.types {
+                    EntryType::All => {}
+                    EntryType::Files => write!(self.stdout, "  files only")?,
+                    EntryType::Dirs => write!(self.stdout, "  dirs only")?,
+                    EntryType::Symlinks => write!(self.stdout, "  symlinks only")?,
+                }
+
+                if self.fd_flags.follow {
+                    write!(self.stdout, "  follow")?;
+                }
+
+                if !self.excludes.is_empty() {
+                    write!(self.stdout, "  excluding {}", self.excludes.join(", "))?;
+                }
+            }
+
+            if self.vim {
+                let name = match self.mode {
+                    Mode::Normal => "normal",
+                    Mode::Insert => "insert",
+                };
+                write!(self.stdout, "  {}", name)?;
+            }
+
+            if self.grep {
+                write!(self.stdout, "  grep")?;
+            } else if !self.extra_args.is_empty() {
+                write!(self.stdout, "  fd {}", shell_words::join(&self.extra_args))?;
+            }
+
+            match self.name_sort {
+                Some(NameSort::Ascending) => write!(self.stdout, "  sorted a-z")?,
+                Some(NameSort::Descending) => write!(self.stdout, "  sorted z-a")?,
+                None => match self.sort_mode {
+                    SortMode::Auto => {}
+                    SortMode::Score => write!(self.stdout, "  by score")?,
+                    SortMode::Depth => write!(self.stdout, "  by depth")?,
+                    SortMode::Name => write!(self.stdout, "  by name")?,
+                },
+            }
+
+            if let Some(frame) = self.last_spinner {
+                write!(self.stdout, "  {} loading", ['|', '/', '-', '\\'][frame])?;
+            }
+
+            if let Some(line) = self.output.get(self.selected).filter(|_| self.show_output) {
+                let path = self
+                    .dir
+                    .join(result_path(&line.data, self.grep))
+                    .components()
+                    .collect();
+                let printed = output_preview(self.config, self.base_dir, path);
+                let printed: String = printed.chars().take(self.term_width as usize).collect();
+                write!(self.stdout, "  → {}", printed)?;
+            }
+
+            if let Some(message) = self.messages.current() {
+                write!(self.stdout, "  {}", message)?;
+            }
+            write!(self.stdout, "{}", Clear(ClearType::UntilNewLine))?;
+
+            // prompt
+            if let Some(typed) = &self.args_prompt {
+                let typed = with_cursor(typed, typed.chars().count());
+                write!(self.stdout, "{} fd args> {}", goto(1, prompt_row), typed)?;
+            } else if let Some(typed) = &self.exclude_prompt {
+                let typed = with_cursor(typed, typed.chars().count());
+                write!(self.stdout, "{} exclude> {}", goto(1, prompt_row), typed)?;
+            } else if let Some(program) = &self.exec_confirm {
+                write!(
+                    self.stdout,
+                    "{} run {}? (y/n)",
+                    goto(1, prompt_row),
+                    program.file_name().unwrap_or_default().to_string_lossy()
+                )?;
+            } else if let Some(pending) = &self.rename {
+                let prompt = if pending.confirm {
+                    format!(" overwrite {}? (y/n)", pending.name)
+                } else {
+                    format!(
+                        " rename> {}",
+                        with_cursor(&pending.name, pending.name.chars().count())
+                    )
+                };
+                write!(self.stdout, "{}{}", goto(1, prompt_row), prompt)?;
+            } else {
+                let dir = self.dir.to_string_lossy();
+                let (prompt, after) = if self.in_bookmarks {
+                    (" bookmarks> ".to_string(), String::new())
+                } else {
+                    let dir = match &self.home {
+                        Some(home) => abbreviate_home(&dir, home),
+                        None => dir.into_owned(),
+                    };
+                    // the directory gives way to what's typed
+                    render_prompt(
+                        &self.prompt_template,
+                        &dir,
+                        self.input.chars().count(),
+                        self.term_width as usize,
+                    )
+                };
+                let (prompt_color, prompt_reset) = match self.theme.prompt.filter(|_| self.color) {
+                    Some(value) => (
+                        SetForegroundColor(Color::AnsiValue(value)).to_string(),
+                        SetForegroundColor(Color::Reset).to_string(),
+                    ),
+                    None => (String::new(), String::new()),
+                };
+                write!(
+                    self.stdout,
+                    "{}{}{}{}",
+                    goto(1, prompt_row),
+                    prompt_color,
+                    prompt,
+                    prompt_reset
+                )?;
+
+                // the cursor goes on the query, or at its end on what the
+                // prompt has after it
+                self.cursor = self.cursor.min(self.input.chars().count());
+                let (typed, after) = if self.cursor < self.input.chars().count() {
+                    (with_cursor(&self.input, self.cursor), after)
+                } else {
+                    (self.input.clone(), with_cursor(&after, 0))
+                };
+
+                if self.flash_input && self.color {
+                    write!(
+                        self.stdout,
+                        "{}{}{}",
+                        SetForegroundColor(Color::DarkRed),
+                        typed,
+                        SetForegroundColor(Color::Reset)
+                    )?;
+                } else {
+                    write!(self.stdout, "{}", typed)?;
+                }
+                write!(self.stdout, "{}{}{}", prompt_color, after, prompt_reset)?;
+
+                // the hidden terminal cursor still goes where typing does,
+                // for input methods that place themselves by it
+                let column = prompt.chars().count() + self.cursor + 1;
+                write!(
+                    self.stdout,
+                    "{}",
+                    goto(column.min(usize::from(u16::MAX)) as u16, prompt_row)
+                )?;
+            }
         }
+        self.stdout.flush()?;
 
-        OutputLine {
-            data,
-            score,
-            indices,
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // run owns the terminal, so it's out of raw mode again by the time an
+    // error gets here to be printed
+    match run().await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("fdi: {}", err);
+            ExitCode::from(2)
         }
     }
+}
+
+/// The exit status for quitting without picking anything, telling it
+/// apart from both a pick and an error for scripts
+const NOTHING_PICKED: u8 = 1;
+
+async fn run() -> Result<ExitCode, Box<dyn Error>> {
+    let mut config = Config::from_args()?;
+    if config.help {
+        print!("{}", USAGE);
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(path) = &config.log_file {
+        FileLog::init(path)?;
+    }
+
+    // a list piped in, like `git ls-files | fdi`, is read like --from-file
+    if config.from_file.is_none() && stdin_piped() {
+        config.from_file = Some(PathBuf::from("-"));
+    }
+    if config.from_file.as_deref() == Some(Path::new("-")) {
+        config.piped = Some(Piped::read_stdin());
+    }
+
+    // where fdi was run from, which --from-file lists are relative to
+    let base_dir = start_dir(&config)?;
+    let dir = match &config.start {
+        Some(start) => resolve_dir(&config, &base_dir.join(start))
+            .ok_or_else(|| format!("not a directory: {}", start.display()))?,
+        None => base_dir.clone(),
+    };
+
+    if config.count {
+        return count_matches(&config, &base_dir, &dir).await;
+    }
+    if config.select_1 {
+        return select_one(&config, &base_dir, &dir).await;
+    }
+
+    // a bad binding is better said before the screen is taken over
+    let config_file = read_config_file();
+    let keymap = load_keymap(config_file.as_ref())?;
+
+    // draw on the terminal itself, leaving stdout free for the accepted
+    // path when it's captured like in `cd "$(fdi)"`, with the keys read
+    // on a thread of their own that's held off while another program has
+    // the terminal, and with it set when the window is resized to pick
+    // up its new size
+    let resized = Arc::new(AtomicBool::new(false));
+    let (tty_input, input_pause) = TtyInput::open(Arc::clone(&resized));
+    let control = config.control.clone().map(read_control);
+    let screen = Screen::open(config.mouse, input_pause)?;
+    let mut app = App::new(
+        &config,
+        &base_dir,
+        dir,
+        config_file.as_ref(),
+        keymap,
+        screen,
+    )
+    .await?;
+
+    'main: loop {
+        std::thread::sleep(Duration::from_millis(3));
+
+        let mut events = Vec::new();
+
+        // the next batch of lines from the listing
+        tokio::select! {
+            Some(event) = app.incoming.recv() => events.push(event),
+            // don't hang on a listing that's slow to produce lines
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+        }
+
+        if resized.swap(false, Ordering::Relaxed) {
+            let (width, height) = app.stdout.size()?;
+            events.push(AppEvent::Resize(width, height));
+        }
+
+        // commands from --control come in as if typed
+        let commands = control.iter().flat_map(|lines| lines.try_iter());
+        events.extend(commands.map(AppEvent::Control));
 
-    pub fn update(&mut self, matcher: &SkimMatcherV2, match_with: &str) -> &mut OutputLine {
-        if let Some((fscore, findices)) = matcher.fuzzy_indices(&self.data, &match_with) {
-            self.score = fscore;
-            self.indices = findices;
+        if let Some(Ok(key)) = tty_input.try_key() {
+            events.push(AppEvent::Key(key));
+        }
+
+        for event in events {
+            if app.handle(event).await? == Action::Exit {
+                break 'main;
+            }
+        }
+        for event in app.tick().await? {
+            if app.handle(event).await? == Action::Exit {
+                break 'main;
+            }
         }
 
-        self
+        app.draw()?;
     }
 
-    pub fn display(&self, term_width: usize) -> String {
-        self.data
-            .char_indices()
-            .take(term_width)
-            .map(move |(i, ch)| {
-                let found = self.indices.iter().find(|&idx| *idx == i);
+    // back out of raw mode first so the trace and the accepted path
+    // come out in lines
+    let App {
+        stdout,
+        trace,
+        accepted,
+        mut collected,
+        mut sink,
+        sent,
+        ..
+    } = app;
+    drop(stdout);
 
-                if found.is_some() {
-                    // color the character
-                    format!("{}{}", color::Fg(color::Red), ch)
-                } else {
-                    format!("{}{}", color::Fg(color::Reset), ch)
-                }
-            })
-            .collect::<Vec<String>>()
-            .join("")
+    if let Some(target) = &config.trace {
+        write_trace(target, &trace)?;
+    }
+
+    // what was collected along the way goes ahead of the final pick
+    for path in accepted {
+        if !collected.contains(&path) {
+            collected.push(path);
+        }
     }
+    // what Alt-Enter printed counts as picked whichever way fdi was quit
+    if collected.is_empty() && sent == 0 {
+        return Ok(ExitCode::from(NOTHING_PICKED));
+    }
+    print_paths(&mut sink, collected)?;
+
+    Ok(ExitCode::SUCCESS)
 }
 
-async fn spawn_fd(dir: &PathBuf) -> Result<Lines<BufReader<ChildStdout>>, Box<dyn Error>> {
-    let mut cmd = Command::new("fd");
+/// Where the accepted paths are printed: stdout, or the --output file,
+/// which is only created once there's a path to write in it
+struct Sink<'a> {
+    config: &'a Config,
+    base_dir: &'a Path,
+    file: Option<std::fs::File>,
+}
 
-    cmd.arg("-H");
-    cmd.current_dir(dir);
+impl<'a> Sink<'a> {
+    pub fn new(config: &'a Config, base_dir: &'a Path) -> Sink<'a> {
+        Sink {
+            config,
+            base_dir,
+            file: None,
+        }
+    }
 
-    // pipe fd stdout to the programs stdout
-    cmd.stdout(Stdio::piped());
+    /// Print `paths` the way --print-paths and --print0 say, flushed
+    /// right away. Nothing to print leaves --output alone
+    pub fn print(&mut self, paths: Vec<PathBuf>) -> std::io::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
 
-    let mut child = cmd.spawn().expect("failed to spawn command");
+        let mut printed = Vec::new();
+        for path in paths {
+            printed.extend(printed_path(self.config, self.base_dir, path));
+        }
 
-    let stdout = child
-        .stdout
-        .take()
-        .expect("child did not have a handle to stdout");
+        let name = match &self.config.output {
+            Some(name) => name,
+            None => {
+                let mut out = std::io::stdout().lock();
+                out.write_all(&printed)?;
+                return out.flush();
+            }
+        };
+        if self.file.is_none() {
+            self.file = Some(std::fs::File::create(name)?);
+        }
+        let file = self.file.as_mut().expect("just created");
+        file.write_all(&printed)?;
+        file.flush()
+    }
+}
 
-    let reader = BufReader::new(stdout).lines();
+/// Print the accepted `paths` to `sink`, going to the first directory
+/// among them instead under --cd
+fn print_paths(sink: &mut Sink, paths: Vec<PathBuf>) -> std::io::Result<()> {
+    // only the first directory picked can be gone to
+    let mut paths = paths;
+    if let Some(nth) = paths
+        .iter()
+        .position(|path| sink.config.cd && path.is_dir())
+    {
+        let dir = paths.remove(nth);
+        write_cd(sink.config.cd_fd, &cd_command(&dir))?;
+    }
 
-    tokio::spawn(async move {
-        let status = child
-            .wait()
-            .await
-            .expect("child process encountered an error");
+    sink.print(paths)
+}
 
-        eprintln!("child status was: {}", status);
-    });
+/// `path` the way it's printed on exit, with the newline or NUL that
+/// ends it
+fn printed_path(config: &Config, base_dir: &Path, path: PathBuf) -> Vec<u8> {
+    let path = match config.print_paths {
+        PathStyle::Absolute => path,
+        PathStyle::Relative => relative_to(&path, base_dir),
+    };
 
-    Ok(reader)
+    let mut printed = if config.shell_quote {
+        shell_quote(&path)
+    } else {
+        path.display().to_string().into_bytes()
+    };
+    printed.push(if config.print0 { b'\0' } else { b'\n' });
+    printed
 }
 
-fn clear_screen(stdout: &mut RawTerminal<StdoutLock>) -> Result<(), Box<dyn Error>> {
-    write!(
-        stdout,
-        "{}{}",
-        termion::clear::All,
-        termion::cursor::Goto(1, 1)
-    )?;
-    stdout.flush()?;
+/// What accepting `path` on its own would print, for the status line,
+/// with a NUL at the end spelled out
+fn output_preview(config: &Config, base_dir: &Path, path: PathBuf) -> String {
+    let printed = if config.cd && path.is_dir() {
+        cd_command(&path)
+    } else {
+        printed_path(config, base_dir, path)
+    };
 
-    Ok(())
+    let printed = String::from_utf8_lossy(&printed);
+    match printed.strip_suffix('\0') {
+        Some(path) => format!("{}\\0", path),
+        None => printed.trim_end_matches('\n').to_string(),
+    }
+}
+
+/// Write the --cd `command` to the --cd-fd descriptor, or else stdout
+#[cfg(unix)]
+fn write_cd(fd: Option<i32>, command: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::io::BorrowedFd;
+
+    let fd = match fd {
+        Some(fd) => fd,
+        None => return std::io::stdout().write_all(command),
+    };
+    // the shell handed the descriptor over and closes it, so it's written
+    // through a copy that's closed here instead. One that isn't open
+    // only fails the copy, with EBADF
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    std::fs::File::from(fd.try_clone_to_owned()?).write_all(command)
+}
+
+/// Write the --cd `command` to stdout, there being no descriptors to pass
+/// on other than that
+#[cfg(not(unix))]
+fn write_cd(fd: Option<i32>, command: &[u8]) -> std::io::Result<()> {
+    match fd {
+        Some(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--cd-fd only works on unix",
+        )),
+        None => std::io::stdout().write_all(command),
+    }
 }
 
-fn update_fuzz(output: &mut Vec<OutputLine>, matcher: &SkimMatcherV2, pattern: &str) {
-    for line in output.iter_mut() {
-        line.update(matcher, pattern);
+/// List `dir` in full and match it against --query without the UI, for
+/// the results best first along with how many lines were listed
+async fn match_listing(
+    config: &Config,
+    base_dir: &Path,
+    dir: &Path,
+) -> Result<(Vec<OutputLine>, usize), Box<dyn Error>> {
+    let matcher = Matcher {
+        scorer: config.algo.scorer(config.case),
+        min_score: config.min_score,
+    };
+    let typed = config.query.as_deref().unwrap_or_default();
+    let pattern = if config.fold_accents {
+        fold_accents(typed)
+    } else {
+        typed.to_string()
+    };
+
+    let mut source = open_source(
+        config,
+        dir,
+        config.depth,
+        FdFlags::new(config),
+        &[],
+        &[],
+        None,
+    )
+    .await?;
+
+    let mut seen = HashSet::new();
+    let mut listed = Vec::new();
+    while let Some(line) = source.next_line().await? {
+        let line = config.trim.apply(line);
+        let line = match config.from_file {
+            Some(_) => list_entry(base_dir, dir, line),
+            None => Some(line),
+        };
+        let line = line
+            .filter(|line| config.keep_root || !is_dir_itself(dir, line))
+            .filter(|line| !config.dedup || seen.insert(line.clone()));
+
+        if let Some(line) = line {
+            listed.push(OutputLine::new(
+                line,
+                config.transform,
+                config.fold_accents,
+                &matcher,
+                &pattern,
+            ));
+        }
     }
 
-    output.sort_by(|a, b| b.score.cmp(&a.score));
+    let mut output = Vec::new();
+    update_fuzz(
+        &mut output,
+        &mut listed,
+        &matcher,
+        &pattern,
+        config.sort,
+        |_| false,
+    );
+    let total = output.len() + listed.len();
+
+    Ok((output, total))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let stdout = stdout();
-    let mut stdout = stdout.lock().into_raw_mode().unwrap();
-    let mut stdin = termion::async_stdin().keys();
-
-    let mut dir = Path::new(".").canonicalize()?;
-
-    // spawn fd
-    // this read will async. read the lines
-    // from stdout
-    let mut reader = spawn_fd(&dir).await?;
-    // we want to record the lines in a vector
-    // so we can do fuzzy searching over it
-    let mut output: Vec<OutputLine> = Vec::new();
-    // get the term height so we don't display more
-    // output than we need
-    let (term_width, term_height) = termion::terminal_size()?;
-    eprintln!("{}, {}", term_width, term_height);
-    let output_offset = 3u16;
-    // just for knowing what the user has typed
-    let mut input = String::new();
-
-    let matcher = SkimMatcherV2::default();
-
-    let exclude_chars = vec!['\n', '\t'];
-
-    clear_screen(&mut stdout)?;
+/// Match the whole listing of `dir` against --query and print the best
+/// match, without the UI, for scripts. Nothing matching exits as if
+/// nothing was picked, and so under --exit-0 does more than one match
+async fn select_one(
+    config: &Config,
+    base_dir: &Path,
+    dir: &Path,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let typed = config.query.as_deref().unwrap_or_default();
+    let (output, _) = match_listing(config, base_dir, dir).await?;
 
-    'main: loop {
-        let key = stdin.next();
+    // no single answer isn't an error, it exits as if nothing was picked
+    match pick_one(&output, config.exit_0) {
+        Pick::Nothing => eprintln!("fdi: nothing matches {}", typed),
+        Pick::Ambiguous(count) => eprintln!("fdi: {} results match {}", count, typed),
+        Pick::Best(best) => {
+            let best = dir.join(&best.data).components().collect();
+            print_paths(&mut Sink::new(config, base_dir), vec![best])?;
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
 
-        // Select the next line from the fd output
-        // and store it into an output buffer
-        tokio::select! {
-            line = reader.next_line() => {
-                if let Ok(line) = line {
-                    if let Some(line) = line {
-                        output.push(OutputLine::new(line, &matcher, &input));
-                        output.sort_by(|a, b| b.score.cmp(&a.score));
-                    }
-                }
-            }
+    Ok(ExitCode::from(NOTHING_PICKED))
+}
+
+/// Print how many lines of the listing of `dir` --query matches out of
+/// how many there are, like `12/340`, without the UI. Nothing matching
+/// exits as if nothing was picked
+async fn count_matches(
+    config: &Config,
+    base_dir: &Path,
+    dir: &Path,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let (output, total) = match_listing(config, base_dir, dir).await?;
+    println!("{}/{}", output.len(), total);
+
+    if output.is_empty() {
+        Ok(ExitCode::from(NOTHING_PICKED))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the terminal, keeping everything drawn on it
+    struct Recorder {
+        drawn: Vec<u8>,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.drawn.extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        // handle the keys
-        if let Some(key) = key {
-            // match on the event sent from stdin
-            if let Ok(key) = key {
-                match key {
-                    // break when ctrl + c is pressed
-                    Key::Ctrl('c') => {
-                        break 'main;
-                    }
-                    // try to change directories on enter
-                    Key::Char('\n') => {
-                        if let Ok(input_dir) = dir.join(&input).canonicalize() {
-                            dir = input_dir;
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
-                            input.clear();
-                            output.clear();
-                            reader = spawn_fd(&dir).await?;
+    impl Terminal for Recorder {
+        fn size(&self) -> std::io::Result<(u16, u16)> {
+            Ok((60, 12))
+        }
 
-                            clear_screen(&mut stdout)?;
-                        }
-                    }
-                    // handle keyboard input
-                    Key::Char(ch) => {
-                        let exclude = exclude_chars.iter().find(|&ex| *ex == ch);
+        fn forget_frame(&mut self) {}
 
-                        if exclude.is_none() {
-                            input.push(ch);
-                            update_fuzz(&mut output, &matcher, &input);
-                            clear_screen(&mut stdout)?;
-                        }
-                    }
-                    // handle the backspace
-                    Key::Backspace => {
-                        if input.len() < 1 {
-                            input.clear();
+        fn run_in_foreground(
+            &mut self,
+            _cmd: std::process::Command,
+        ) -> Result<ExitStatus, Box<dyn Error>> {
+            Err("no terminal to give".into())
+        }
+    }
 
-                            // go up to the parent directory
-                            if let Some(parent_dir) = dir.parent() {
-                                dir = PathBuf::from(parent_dir);
-                                output.clear();
-                                reader = spawn_fd(&dir).await?;
-                            }
-                        } else {
-                            input = input.chars().take(input.len() - 1).collect::<String>();
-                            update_fuzz(&mut output, &matcher, &input);
-                        }
+    /// A directory of its own for `test` to move around in, with `d` in it
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fdi-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(dir.join("d")).unwrap();
+        dir
+    }
 
-                        // Make sure the screen gets a full clear when the backspace happens
-                        clear_screen(&mut stdout)?;
-                    }
-                    _ => {}
-                }
+    /// Lists `lines` as piped in, so nothing has to run fd
+    fn piped_config(lines: &[&str]) -> Config {
+        Config {
+            from_file: Some(PathBuf::from("-")),
+            piped: Some(Piped::from_lines(
+                lines.iter().map(|line| line.to_string()).collect(),
+            )),
+            ..Config::default()
+        }
+    }
+
+    async fn open_app<'a>(config: &'a Config, base_dir: &'a Path) -> App<'a, Recorder> {
+        let recorder = Recorder { drawn: Vec::new() };
+        let mut app = App::new(
+            config,
+            base_dir,
+            base_dir.to_path_buf(),
+            None,
+            HashMap::new(),
+            recorder,
+        )
+        .await
+        .unwrap();
+
+        take_in(&mut app).await;
+        app
+    }
+
+    /// Handle what comes in until nothing more does for a while, like
+    /// the listing's lines
+    async fn take_in(app: &mut App<'_, Recorder>) {
+        let wait = Duration::from_millis(50);
+        while let Ok(Some(event)) = tokio::time::timeout(wait, app.incoming.recv()).await {
+            app.handle(event).await.unwrap();
+        }
+    }
+
+    /// A listing of `lines` that are all there to be read at once
+    fn listed_source(lines: &str) -> Source {
+        let lines: Box<dyn AsyncRead + Unpin + Send> =
+            Box::new(std::io::Cursor::new(lines.as_bytes().to_vec()));
+
+        Source {
+            lines: BufReader::new(lines).lines(),
+            status: None,
+            started: Instant::now(),
+            kill: None,
+            cancel: Cancel::default(),
+        }
+    }
+
+    fn listed(app: &App<Recorder>) -> Vec<String> {
+        app.output.iter().map(|line| line.data.clone()).collect()
+    }
+
+    /// What was drawn with the escape sequences taken out, so a match's
+    /// highlighting doesn't break up the text around it
+    fn visible(drawn: &[u8]) -> String {
+        let drawn = String::from_utf8_lossy(drawn);
+        let mut text = String::new();
+        let mut chars = drawn.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                // up to and including the letter that ends the sequence
+                chars.by_ref().skip(1).find(|c| c.is_ascii_alphabetic());
+            } else {
+                text.push(c);
             }
         }
+        text
+    }
+
+    async fn type_query(app: &mut App<'_, Recorder>, query: &str) {
+        for c in query.chars() {
+            app.handle(AppEvent::Key(Key::Char(c))).await.unwrap();
+        }
+        // rather than wait out matching being put off while typing
+        app.match_at = Some(Instant::now());
+        app.tick().await.unwrap();
+        take_in(app).await;
+    }
+
+    #[tokio::test]
+    async fn dir_lists_the_directory_moved_into() {
+        let base_dir = scratch_dir("dir");
+        let config = piped_config(&["a", "d/x", "d/y"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        let action = app.handle(AppEvent::Dir(base_dir.join("d"))).await.unwrap();
+        assert_eq!(action, Action::Continue);
+        assert_eq!(app.dir, base_dir.join("d"));
+        assert_eq!(app.prev_dir.as_deref(), Some(base_dir.as_path()));
+        assert_eq!(app.history.at, 1);
+        // nothing from the directory left behind while this one settles
+        assert!(app.output.is_empty());
+
+        app.settle_at = Some(Instant::now());
+        app.tick().await.unwrap();
+        take_in(&mut app).await;
+        assert_eq!(listed(&app), ["x", "y"]);
+    }
+
+    #[tokio::test]
+    async fn lines_from_before_a_dir_change_are_dropped() {
+        let base_dir = scratch_dir("stale");
+        let config = piped_config(&["a", "d/x"]);
+        let mut app = open_app(&config, &base_dir).await;
+        let before = app.source.generation;
+
+        app.handle(AppEvent::Dir(base_dir.join("d"))).await.unwrap();
+        app.settle_at = Some(Instant::now());
+        app.tick().await.unwrap();
+        take_in(&mut app).await;
+
+        let late = AppEvent::Lines(before, vec!["d/late".to_string()]);
+        app.handle(late).await.unwrap();
+        assert_eq!(listed(&app), ["x"]);
+    }
+
+    #[tokio::test]
+    async fn accept_exits_with_the_selected_path() {
+        let base_dir = scratch_dir("accept");
+        let config = piped_config(&["a", "b"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        app.handle(AppEvent::Move(1)).await.unwrap();
+        let action = app.handle(AppEvent::Key(Key::Char('\n'))).await.unwrap();
+        assert_eq!(action, Action::Exit);
+        assert_eq!(app.accepted, [base_dir.join(&listed(&app)[1])]);
+    }
+
+    #[tokio::test]
+    async fn quit_exits_with_nothing_accepted() {
+        let base_dir = scratch_dir("quit");
+        let config = piped_config(&["a"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        let action = app.handle(AppEvent::Key(Key::Esc)).await.unwrap();
+        assert_eq!(action, Action::Exit);
+        assert!(app.accepted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn query_narrows_what_is_drawn() {
+        let base_dir = scratch_dir("query");
+        let config = piped_config(&["apple", "banana", "cherry"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        type_query(&mut app, "ban").await;
+        assert_eq!(app.input, "ban");
+        assert_eq!(listed(&app), ["banana"]);
+
+        app.stdout.drawn.clear();
+        app.render().unwrap();
+        let drawn = visible(&app.stdout.drawn);
+        assert!(drawn.contains("banana"));
+        assert!(!drawn.contains("cherry"));
+    }
+
+    #[tokio::test]
+    async fn late_sorted_for_an_old_query_is_dropped() {
+        let base_dir = scratch_dir("sorted");
+        let config = piped_config(&["apple", "banana", "cherry"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        // the matching for "a" is still underway when "b" is typed
+        app.handle(AppEvent::Input("a".to_string())).await.unwrap();
+        app.match_at = Some(Instant::now());
+        app.tick().await.unwrap();
+        let old = app.matching.current();
+        type_query(&mut app, "b").await;
+        assert_eq!(listed(&app), ["banana"]);
+
+        let output = app.unmatched.clone();
+        let late = Sorted {
+            generation: old,
+            output,
+            unmatched: Vec::new(),
+        };
+        app.handle(AppEvent::Sorted(late)).await.unwrap();
+        assert_eq!(listed(&app), ["banana"]);
+    }
 
-        // output the up to the term height of
-        // lines from the command output
-        let cmd_output = output
+    #[tokio::test]
+    async fn lines_listed_while_sorting_are_kept() {
+        let base_dir = scratch_dir("arrived");
+        let config = piped_config(&["apple", "banana", "cherry"]);
+        let mut app = open_app(&config, &base_dir).await;
+
+        app.handle(AppEvent::Input("b".to_string())).await.unwrap();
+        app.match_at = Some(Instant::now());
+        app.tick().await.unwrap();
+        let more = AppEvent::Lines(app.source.generation, vec!["blueberry".to_string()]);
+        app.handle(more).await.unwrap();
+        take_in(&mut app).await;
+
+        let mut kept = listed(&app);
+        kept.sort();
+        assert_eq!(kept, ["banana", "blueberry"]);
+    }
+
+    /// A job matching `lines` against `pattern` for the sort thread
+    fn sort_job(generation: u64, lines: &[&str], pattern: &str) -> SortJob {
+        let matcher = Arc::new(Matcher {
+            scorer: Algo::Skim.scorer(CaseMode::default()),
+            min_score: 0,
+        });
+        let output = lines
             .iter()
-            .take(term_height as usize - (output_offset + 0) as usize)
-            .map(|line| line.display(term_width as usize))
-            .collect::<Vec<String>>()
-            .join("\n\r");
+            .map(|line| {
+                let line = line.to_string();
+                OutputLine::new(line, Transform::default(), false, &matcher, "")
+            })
+            .collect();
 
-        write!(
-            stdout,
-            "{}{}{}",
-            termion::cursor::Goto(1, output_offset),
-            cmd_output,
-            color::Fg(color::Reset)
-        )?;
+        SortJob {
+            generation,
+            output,
+            unmatched: Vec::new(),
+            matcher,
+            pattern: pattern.to_string(),
+            sort_mode: SortMode::default(),
+            pinned: Vec::new(),
+            dir: PathBuf::new(),
+        }
+    }
 
-        // progress indicator of sorts
-        let total = output.len();
-        let results = output.len();
-        write!(
-            stdout,
-            "{} {}/{}",
-            termion::cursor::Goto(1, 2),
-            results,
-            total
-        )?;
+    fn next_sorted(incoming: &mut mpsc::UnboundedReceiver<AppEvent>) -> Sorted {
+        match incoming.blocking_recv() {
+            Some(AppEvent::Sorted(sorted)) => sorted,
+            other => panic!("expected the sorted results, got {:?}", other),
+        }
+    }
 
-        // prompt
-        write!(
-            stdout,
-            "{} > {} {}",
-            termion::cursor::Goto(1, 1),
-            dir.to_string_lossy(),
-            input,
-        )?;
-        stdout.flush()?;
+    #[test]
+    fn sort_thread_orders_by_score() {
+        let (events, mut incoming) = mpsc::unbounded_channel();
+        let latest = Generation::default();
+        let sorter = spawn_sorter(events, latest.clone());
 
-        std::thread::sleep(Duration::from_millis(3));
+        let lines = ["src/main.rs", "map.rs", "docs/m/a/p.md", "lib.rs"];
+        let generation = latest.next();
+        sorter.send(sort_job(generation, &lines, "map")).unwrap();
+
+        let sorted = next_sorted(&mut incoming);
+        assert_eq!(sorted.generation, generation);
+        let order: Vec<_> = sorted
+            .output
+            .iter()
+            .map(|line| line.data.as_str())
+            .collect();
+        assert_eq!(order, ["map.rs", "docs/m/a/p.md"]);
+        assert_eq!(sorted.unmatched.len(), 2);
     }
 
-    Ok(())
+    #[test]
+    fn sort_thread_skips_a_pattern_typed_over() {
+        let (events, mut incoming) = mpsc::unbounded_channel();
+        let latest = Generation::default();
+        let sorter = spawn_sorter(events, latest.clone());
+
+        // "ma" and then "map" come in before the thread gets to either
+        let lines = ["main.rs", "map.rs"];
+        let old = latest.next();
+        let new = latest.next();
+        sorter.send(sort_job(old, &lines, "ma")).unwrap();
+        sorter.send(sort_job(new, &lines, "map")).unwrap();
+
+        let sorted = next_sorted(&mut incoming);
+        assert_eq!(sorted.generation, new);
+        assert_eq!(sorted.output.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn killed_listing_sends_no_more_lines() {
+        let (events, mut incoming) = mpsc::unbounded_channel();
+        let mut killed = listed_source("a\nb\n").listen(0, events.clone());
+        killed.kill();
+        let _kept = listed_source("c\n").listen(1, events);
+
+        let mut sent = Vec::new();
+        while let Some(event) = incoming.recv().await {
+            if let AppEvent::Lines(_, batch) = event {
+                sent.extend(batch);
+            }
+        }
+        assert_eq!(sent, ["c"]);
+    }
 }